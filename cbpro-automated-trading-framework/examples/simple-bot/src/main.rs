@@ -1,41 +1,52 @@
-use cbpro_automated_trading_framework::{Strategy, TradingData};
+use cbpro_automated_trading_framework::execution::{ExecutionReport, OrderIntent};
+use cbpro_automated_trading_framework::{async_trait, Strategy, TradingData, TradingStrategy};
 use coinbase_pro_api_rust::client::AuthorizedClient;
-use coinbase_pro_api_rust::orders::Order;
-use rust_decimal::prelude::{Decimal, FromStr};
+use coinbase_pro_api_rust::environment::Environment;
+use coinbase_pro_api_rust::feed::Ticker;
+use coinbase_pro_api_rust::orders::{NewOrder, Side};
+use rust_decimal::prelude::Decimal;
 
-fn main() {
-    let my_user = AuthorizedClient::new(
-                    "https://api-public.sandbox.pro.coinbase.com",
-                    "93fb3fb63801d68af56a4c8aee61aec0",
-                    "sandbox",
-                    "P7hJ+jTTOJpx2nlviiFyPWxRT3F1rQcZ/onLTGjNxDCRE5DtqHde+SOMlOepwVJlSWbY76Bjg1E5h8btA6U/wg==");
-    
-    struct MyData {
-        trades_made: u32,
-    }
+struct MyStrategy {
+    trades_made: u32,
+}
 
-    let my_data = MyData { trades_made: 0 };
+#[async_trait]
+impl TradingStrategy for MyStrategy {
+    async fn on_ticker(&mut self, _data: &TradingData, ticker: &Ticker) -> Vec<OrderIntent> {
+        if ticker.product_id == "BTC-USD" && ticker.price > Decimal::new(30000, 0) && self.trades_made < 5 {
+            return vec![OrderIntent::Place(NewOrder::market("BTC-USD", Side::Sell, Decimal::new(1, 3)))];
+        }
+        Vec::new()
+    }
 
-    fn my_strat(user: &AuthorizedClient, data: &mut TradingData<MyData>) {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        let action_block = async {
-            if data.products["BTC-USD"].price > Decimal::new(30000, 0) && data.user_data.trades_made < 5 {
-                user.place_order(Order::MarketOrder, "sell", "BTC-USD", None, "0.001").await;
-                println!("Placed sell order #{}", data.user_data.trades_made);
-                data.user_data.trades_made += 1;
+    async fn on_execution(&mut self, _data: &TradingData, report: &ExecutionReport) -> Vec<OrderIntent> {
+        match report {
+            ExecutionReport::Accepted { .. } => {
+                println!("Placed sell order #{}", self.trades_made);
+                self.trades_made += 1;
             }
-        };
-        rt.block_on(action_block);
+            ExecutionReport::Rejected { reason, .. } => println!("Order failed: {}", reason),
+            _ => {}
+        }
+        Vec::new()
     }
+}
+
+#[tokio::main]
+async fn main() {
+    let my_user = AuthorizedClient::with_environment(
+                    Environment::Sandbox,
+                    "93fb3fb63801d68af56a4c8aee61aec0",
+                    "sandbox",
+                    "P7hJ+jTTOJpx2nlviiFyPWxRT3F1rQcZ/onLTGjNxDCRE5DtqHde+SOMlOepwVJlSWbY76Bjg1E5h8btA6U/wg==")
+                    .expect("invalid API credentials");
 
     let mut my_strategy = Strategy::new(
                     "Ben's simple strategy",
                     my_user,
                     vec!["BTC-USD"],
-                    my_strat,
-                    my_data,
+                    MyStrategy { trades_made: 0 },
     );
-    my_strategy.subscribe_to_ticker_data();
-    my_strategy.run();
+    my_strategy.subscribe_to_ticker_data().await;
+    my_strategy.run().await;
 }
-
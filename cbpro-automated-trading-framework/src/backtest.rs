@@ -0,0 +1,272 @@
+use crate::simulation::{SimulatedBalance, SimulatedExchange, TradeRecord};
+use crate::{Strategy, TradingStrategy};
+use chrono::{DateTime, Utc};
+use coinbase_pro_api_rust::recorder::RecordedMessage;
+use coinbase_pro_api_rust::websocket::{ConnectionState, FeedEvent};
+use rust_decimal::prelude::Decimal;
+use std::time::Duration;
+
+/// Value of the account at a point in the replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct EquityPoint {
+    pub time: DateTime<Utc>,
+    pub equity: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestReport {
+    pub trades: Vec<TradeRecord>,
+    pub equity_curve: Vec<EquityPoint>,
+    pub balances: Vec<SimulatedBalance>,
+}
+
+impl BacktestReport {
+    pub fn fees(&self) -> Decimal {
+        self.trades.iter().map(|trade| trade.fee).sum()
+    }
+
+    pub fn final_equity(&self) -> Option<Decimal> {
+        self.equity_curve.last().map(|point| point.equity)
+    }
+
+    /// Largest fall from a peak of the equity curve, as a fraction of the peak
+    pub fn max_drawdown(&self) -> Decimal {
+        let mut peak = Decimal::ZERO;
+        let mut drawdown = Decimal::ZERO;
+        for point in &self.equity_curve {
+            peak = peak.max(point.equity);
+            if peak > Decimal::ZERO {
+                drawdown = drawdown.max((peak - point.equity) / peak);
+            }
+        }
+        drawdown
+    }
+}
+
+/// Replays recorded feed messages through a strategy, with its intents filled by a simulated
+/// exchange. Timers fire on the recorded clock rather than the wall clock.
+pub struct Backtest<S> {
+    strategy: Strategy<S>,
+    exchange: SimulatedExchange,
+    quote_currency: String,
+    equity_interval: Duration,
+}
+
+impl<S: TradingStrategy> Backtest<S> {
+    /// `exchange` should already hold the starting balances. Equity is valued in `quote_currency`.
+    pub fn new(name: &str, products: Vec<&str>, strategy: S, exchange: SimulatedExchange, quote_currency: &str) -> Self {
+        Backtest {
+            // no feed - messages come from the records instead
+            strategy: Strategy::build(name, None, Box::new(exchange.clone()), products, strategy),
+            exchange,
+            quote_currency: quote_currency.to_string(),
+            equity_interval: Duration::from_secs(60),
+        }
+    }
+
+    pub fn strategy(&self) -> &S {
+        self.strategy.strategy()
+    }
+
+    pub fn exchange(&self) -> &SimulatedExchange {
+        &self.exchange
+    }
+
+    /// Calls `TradingStrategy::on_timer` every `period` of recorded time
+    pub fn set_timer(&mut self, period: Duration) {
+        self.strategy.set_timer(period);
+    }
+
+    /// How often the equity curve is sampled, in recorded time. Defaults to a minute.
+    pub fn set_equity_interval(&mut self, period: Duration) {
+        self.equity_interval = period;
+    }
+
+    pub async fn run<I: IntoIterator<Item = RecordedMessage>>(&mut self, records: I) -> BacktestReport {
+        let timer = self.strategy.timer.and_then(|period| chrono::Duration::from_std(period).ok()).filter(|period| !period.is_zero());
+        let equity_interval = chrono::Duration::from_std(self.equity_interval).unwrap_or_else(|_| chrono::Duration::minutes(1));
+        let mut equity_curve = Vec::new();
+        let mut next_tick = None;
+        let mut next_sample = None;
+        let mut last_time = None;
+
+        self.strategy.data.connection = ConnectionState::Connected;
+        for record in records {
+            let time = record.received;
+            self.exchange.set_time(time);
+            if last_time.is_none() {
+                self.strategy.start().await;
+                next_tick = timer.map(|period| time + period);
+            }
+            last_time = Some(time);
+            while let (Some(tick), Some(period)) = (next_tick, timer) {
+                if tick > time {
+                    break;
+                }
+                self.exchange.set_time(tick);
+                self.strategy.timer_tick().await;
+                next_tick = Some(tick + period);
+            }
+            self.exchange.set_time(time);
+            if let Some(message) = record.message() {
+                self.strategy.handle_event(FeedEvent::Message(message)).await;
+            }
+            let sample_due = match next_sample { Some(sample) => time >= sample, None => true };
+            if sample_due {
+                equity_curve.push(self.equity_point(time));
+                next_sample = Some(time + equity_interval);
+            }
+        }
+        if let Some(time) = last_time {
+            self.strategy.stop().await;
+            equity_curve.push(self.equity_point(time));
+        }
+
+        BacktestReport {
+            trades: self.exchange.trades(),
+            equity_curve,
+            balances: self.exchange.balances(),
+        }
+    }
+
+    fn equity_point(&self, time: DateTime<Utc>) -> EquityPoint {
+        EquityPoint { time, equity: self.exchange.equity(&self.quote_currency) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::OrderIntent;
+    use crate::simulation::Fees;
+    use crate::TradingData;
+    use async_trait::async_trait;
+    use chrono::TimeZone;
+    use coinbase_pro_api_rust::feed::{FeedMessage, Ticker};
+    use coinbase_pro_api_rust::orders::{NewOrder, Side};
+    use rust_decimal_macros::dec;
+
+    /// Buys one BTC on the first ticker, and notes the price each time its timer fires
+    #[derive(Default)]
+    struct BuyOnce {
+        bought: bool,
+        timer_prices: Vec<Decimal>,
+    }
+
+    #[async_trait]
+    impl TradingStrategy for BuyOnce {
+        async fn on_ticker(&mut self, _data: &TradingData, _ticker: &Ticker) -> Vec<OrderIntent> {
+            if self.bought {
+                return Vec::new();
+            }
+            self.bought = true;
+            vec![OrderIntent::Place(NewOrder::market("BTC-USD", Side::Buy, dec!(1)))]
+        }
+
+        async fn on_timer(&mut self, data: &TradingData) -> Vec<OrderIntent> {
+            self.timer_prices.push(data.products["BTC-USD"].price);
+            Vec::new()
+        }
+    }
+
+    fn at(minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 5, 1, 12, minute, second).unwrap()
+    }
+
+    /// Ticker for BTC-USD without a trade, received at `received`
+    fn ticker(sequence: u64, price: Decimal, received: DateTime<Utc>) -> RecordedMessage {
+        let ticker = FeedMessage::Ticker(Ticker {
+            sequence,
+            product_id: "BTC-USD".to_string(),
+            price,
+            open_24h: price,
+            volume_24h: dec!(1000),
+            low_24h: price,
+            high_24h: price,
+            volume_30d: dec!(30000),
+            best_bid: price - dec!(1),
+            best_ask: price + dec!(1),
+            side: None,
+            time: None,
+            trade_id: None,
+            last_size: None,
+        });
+        RecordedMessage::new(received, sequence, &ticker)
+    }
+
+    fn backtest() -> Backtest<BuyOnce> {
+        let exchange = SimulatedExchange::new(Fees { maker: dec!(0.001), taker: dec!(0.002) });
+        exchange.deposit("USD", dec!(1000));
+        let mut backtest = Backtest::new("backtest", vec!["BTC-USD"], BuyOnce::default(), exchange, "USD");
+        backtest.set_timer(Duration::from_secs(60));
+        backtest
+    }
+
+    fn curve(equity: &[Decimal]) -> BacktestReport {
+        BacktestReport {
+            trades: Vec::new(),
+            equity_curve: equity.iter().enumerate().map(|(i, equity)| EquityPoint { time: at(i as u32, 0), equity: *equity }).collect(),
+            balances: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn max_drawdown_is_the_largest_fall_from_a_peak() {
+        let report = curve(&[dec!(100), dec!(120), dec!(90), dec!(130), dec!(117)]);
+        assert_eq!(report.max_drawdown(), dec!(0.25));
+        assert_eq!(report.final_equity(), Some(dec!(117)));
+        assert_eq!(curve(&[dec!(100), dec!(110), dec!(120)]).max_drawdown(), dec!(0));
+        let empty = curve(&[]);
+        assert_eq!(empty.max_drawdown(), dec!(0));
+        assert_eq!(empty.final_equity(), None);
+    }
+
+    #[tokio::test]
+    async fn reports_fees_and_equity_from_the_simulated_exchange() {
+        let mut backtest = backtest();
+        let report = backtest.run(vec![
+            ticker(1, dec!(100), at(0, 0)),
+            ticker(2, dec!(90), at(0, 30)),
+            ticker(3, dec!(80), at(1, 30)),
+            ticker(4, dec!(120), at(2, 30)),
+        ]).await;
+
+        // bought at the 101 ask as taker
+        assert_eq!(report.trades.len(), 1);
+        assert_eq!(report.fees(), dec!(0.202));
+        let cash = dec!(1000) - dec!(101) - dec!(0.202);
+        // sampled on the first message, then once a minute has passed, and when the run ends
+        let equity: Vec<(DateTime<Utc>, Decimal)> = report.equity_curve.iter().map(|point| (point.time, point.equity)).collect();
+        assert_eq!(equity, vec![
+            (at(0, 0), cash + dec!(100)),
+            (at(1, 30), cash + dec!(80)),
+            (at(2, 30), cash + dec!(120)),
+            (at(2, 30), cash + dec!(120)),
+        ]);
+        assert_eq!(report.final_equity(), Some(cash + dec!(120)));
+        assert_eq!(report.max_drawdown(), dec!(20) / (cash + dec!(100)));
+    }
+
+    #[tokio::test]
+    async fn timers_fire_on_the_recorded_clock() {
+        let mut backtest = backtest();
+        // the whole run takes no wall clock time, but spans two and a half recorded minutes
+        backtest.run(vec![
+            ticker(1, dec!(100), at(0, 0)),
+            ticker(2, dec!(90), at(0, 30)),
+            ticker(3, dec!(80), at(1, 30)),
+            ticker(4, dec!(120), at(2, 30)),
+        ]).await;
+        // at 12:01 and 12:02, each before the next message after it
+        assert_eq!(backtest.strategy().timer_prices, vec![dec!(90), dec!(80)]);
+    }
+
+    #[tokio::test]
+    async fn nothing_happens_without_records() {
+        let mut backtest = backtest();
+        let report = backtest.run(Vec::new()).await;
+        assert!(report.trades.is_empty());
+        assert!(report.equity_curve.is_empty());
+        assert!(backtest.strategy().timer_prices.is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use async_trait::async_trait;
+use coinbase_pro_api_rust::client::AuthorizedClient;
+use coinbase_pro_api_rust::feed::FeedMessage;
+use coinbase_pro_api_rust::orders::NewOrder;
+use coinbase_pro_api_rust::user_channel::OrderFill;
+
+/// What a strategy hook asks the execution layer to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderIntent {
+    /// Any order the exchange takes, e.g. `NewOrder::limit(..).post_only().client_oid(id)`
+    Place(NewOrder),
+    Cancel { order_id: String },
+    /// Cancel the order placed with `NewOrder::client_oid`, before or after its id is known
+    CancelByClientOid { client_oid: String },
+    /// Cancel every open order, or only those for `product_id`
+    CancelAll { product_id: Option<String> },
+    /// Cancel `order_id` and place `order` only once the cancel succeeds
+    Replace { order_id: String, order: NewOrder },
+}
+
+/// Outcome of an intent (or of market data, for simulated executors), passed back to the strategy
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionReport {
+    Accepted { order_id: String, order: NewOrder },
+    Rejected { order: NewOrder, reason: String },
+    Canceled { order_id: String },
+    /// `order_id` is the client_oid when the cancel was by client_oid
+    CancelRejected { order_id: String, reason: String },
+    CancelAllRejected { product_id: Option<String>, reason: String },
+    Fill(OrderFill),
+}
+
+/// Where intents go - the live exchange, a paper trader or a backtest
+#[async_trait]
+pub trait ExecutionLayer: Send {
+    async fn place(&mut self, order: NewOrder) -> Vec<ExecutionReport>;
+
+    async fn cancel(&mut self, order_id: &str) -> Vec<ExecutionReport>;
+
+    async fn cancel_by_client_oid(&mut self, client_oid: &str) -> Vec<ExecutionReport>;
+
+    /// Reports each canceled order - none at all if nothing was open
+    async fn cancel_all(&mut self, product_id: Option<&str>) -> Vec<ExecutionReport>;
+
+    /// Sees every feed message before the strategy does - simulated executors fill resting orders here
+    async fn on_market_data(&mut self, _message: &FeedMessage) -> Vec<ExecutionReport> {
+        Vec::new()
+    }
+
+    async fn execute(&mut self, intent: OrderIntent) -> Vec<ExecutionReport> {
+        match intent {
+            OrderIntent::Place(order) => self.place(order).await,
+            OrderIntent::Cancel { order_id } => self.cancel(&order_id).await,
+            OrderIntent::CancelByClientOid { client_oid } => self.cancel_by_client_oid(&client_oid).await,
+            OrderIntent::CancelAll { product_id } => self.cancel_all(product_id.as_deref()).await,
+            OrderIntent::Replace { order_id, order } => {
+                let mut reports = self.cancel(&order_id).await;
+                let canceled = reports.iter().any(|r| matches!(r, ExecutionReport::Canceled { .. }));
+                if canceled {
+                    reports.extend(self.place(order).await);
+                } else {
+                    reports.push(ExecutionReport::Rejected { order, reason: format!("order {} was not canceled", order_id) });
+                }
+                reports
+            },
+        }
+    }
+}
+
+/// Sends intents to the exchange. Fills arrive through the `user` channel rather than as reports.
+pub struct LiveExecution {
+    client: AuthorizedClient,
+}
+
+impl LiveExecution {
+    pub fn new(client: AuthorizedClient) -> Self {
+        LiveExecution { client }
+    }
+
+    pub fn client(&self) -> &AuthorizedClient {
+        &self.client
+    }
+}
+
+#[async_trait]
+impl ExecutionLayer for LiveExecution {
+    async fn place(&mut self, order: NewOrder) -> Vec<ExecutionReport> {
+        vec![match self.client.place_order(&order).await {
+            Ok(response) => ExecutionReport::Accepted { order_id: response.id().to_string(), order },
+            Err(e) => ExecutionReport::Rejected { order, reason: e.to_string() },
+        }]
+    }
+
+    async fn cancel(&mut self, order_id: &str) -> Vec<ExecutionReport> {
+        vec![match self.client.cancel_order(order_id).await {
+            Ok(canceled) => ExecutionReport::Canceled { order_id: canceled.id },
+            Err(e) => ExecutionReport::CancelRejected { order_id: order_id.to_string(), reason: e.to_string() },
+        }]
+    }
+
+    async fn cancel_by_client_oid(&mut self, client_oid: &str) -> Vec<ExecutionReport> {
+        vec![match self.client.cancel_order_by_client_oid(client_oid).await {
+            Ok(canceled) => ExecutionReport::Canceled { order_id: canceled.id },
+            Err(e) => ExecutionReport::CancelRejected { order_id: client_oid.to_string(), reason: e.to_string() },
+        }]
+    }
+
+    async fn cancel_all(&mut self, product_id: Option<&str>) -> Vec<ExecutionReport> {
+        match self.client.cancel_all(product_id).await {
+            Ok(canceled) => canceled.into_iter().map(|order| ExecutionReport::Canceled { order_id: order.id }).collect(),
+            Err(e) => vec![ExecutionReport::CancelAllRejected { product_id: product_id.map(str::to_string), reason: e.to_string() }],
+        }
+    }
+}
@@ -1,13 +1,43 @@
-use coinbase_pro_api_rust::client::AuthorizedClient;
-use std::thread;
+pub mod backtest;
+pub mod execution;
+pub mod simulation;
+
+use coinbase_pro_api_rust::client::{AuthorizedClient, PublicClient};
+use coinbase_pro_api_rust::environment::Environment;
+use coinbase_pro_api_rust::errors::{FeedError, RequestError};
+use coinbase_pro_api_rust::feed::{FeedMessage, Ticker};
+use coinbase_pro_api_rust::level2_feed::OrderBook;
+use coinbase_pro_api_rust::market_data::{ProductStats, ProductTicker};
+use coinbase_pro_api_rust::orders::Side;
+use coinbase_pro_api_rust::replay::Replayer;
+use coinbase_pro_api_rust::sequence::{SequenceCheck, SequenceGap, SequenceTracker};
+use coinbase_pro_api_rust::user_channel::OrderFill;
+use coinbase_pro_api_rust::websocket::{ConnectionState, FeedConnection, FeedEvent};
+pub use async_trait::async_trait;
+use execution::{ExecutionLayer, ExecutionReport, LiveExecution, OrderIntent};
+use simulation::SimulatedExchange;
+use std::collections::{HashMap, VecDeque};
 use std::time::Duration;
-use serde_json::Value;
-use std::collections::HashMap;
-use rust_decimal::prelude::{Decimal, FromStr};
+use rust_decimal::prelude::Decimal;
+use tokio::time::{interval, Interval, MissedTickBehavior};
+
+/// Channels `resync` resubscribes to get fresh state for a product
+const MARKET_DATA_CHANNELS: &[&str] = &["ticker", "level2"];
 
-pub struct TradingData<T> {
+pub struct TradingData {
     pub products: HashMap<String, ProductData>,
-    pub user_data: T,
+    pub books: HashMap<String, OrderBook>,
+    /// Products whose feed skipped or reordered messages and are being resubscribed -
+    /// their data may be stale until the entry is cleared
+    pub gaps: HashMap<String, SequenceGap>,
+    /// Last known state of the feed connection - data goes stale while it isn't `Connected`
+    pub connection: ConnectionState,
+}
+
+impl TradingData {
+    pub fn is_synced(&self, product_id: &str) -> bool {
+        !self.gaps.contains_key(product_id)
+    }
 }
 
 pub struct ProductData {
@@ -19,89 +49,512 @@ pub struct ProductData {
     pub low_24h: Decimal,
     pub volume_24h: Decimal,
     pub volume_30d: Decimal,
-    pub last_trade: Trade,
+    /// Not known until the first trade after subscribing
+    pub last_trade: Option<Trade>,
 }
 
+impl ProductData {
+    /// From the REST endpoints, for before the first ticker message arrives
+    pub fn from_rest(product_id: &str, ticker: &ProductTicker, stats: &ProductStats) -> Self {
+        ProductData {
+            product_id: product_id.to_string(),
+            price: ticker.price,
+            best_bid: ticker.bid,
+            best_ask: ticker.ask,
+            high_24h: stats.high,
+            low_24h: stats.low,
+            volume_24h: stats.volume,
+            volume_30d: stats.volume_30day.unwrap_or_default(),
+            last_trade: None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Trade {
     pub trade_id: u64,
     pub price: Decimal,
-    pub side: String,
+    pub side: Side,
     pub size: Decimal,
 }
 
-pub struct Strategy<T> {
+/// Trading logic driven by `Strategy::run`. Hooks return the orders they want placed or canceled
+/// rather than calling the exchange, so the same strategy can run live, on paper or in a backtest.
+/// Every hook defaults to doing nothing, and any state the strategy needs lives in the
+/// implementing struct, e.g.
+///
+/// ~~~ignore
+/// #[async_trait]
+/// impl TradingStrategy for MyStrategy {
+///     async fn on_ticker(&mut self, data: &TradingData, ticker: &Ticker) -> Vec<OrderIntent> { ... }
+/// }
+/// ~~~
+#[async_trait]
+pub trait TradingStrategy: Send {
+    /// Called once when `run` starts, before any feed data arrives
+    async fn on_start(&mut self, _data: &TradingData) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called for every in-order ticker, after `data.products` is updated
+    async fn on_ticker(&mut self, _data: &TradingData, _ticker: &Ticker) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called after `data.books[product_id]` receives a snapshot or update
+    async fn on_book_update(&mut self, _data: &TradingData, _product_id: &str) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called when one of our orders fills - live trading needs `subscribe_to_user_data`
+    async fn on_fill(&mut self, _data: &TradingData, _fill: &OrderFill) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called with the outcome of every intent other than fills
+    async fn on_execution(&mut self, _data: &TradingData, _report: &ExecutionReport) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called when the feed connects, drops or fails to reconnect, after `data.connection` is
+    /// updated. Books are cleared on a disconnect until fresh snapshots arrive.
+    async fn on_connection(&mut self, _data: &TradingData, _state: &ConnectionState) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called when a product's feed skipped messages, after its data was dropped and resubscribed -
+    /// the gap stays in `data.gaps` until fresh data arrives
+    async fn on_gap(&mut self, _data: &TradingData, _gap: &SequenceGap) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called when the feed rejects a request, e.g. a `user` subscription with bad credentials, or
+    /// sends something that can't be read
+    async fn on_feed_error(&mut self, _data: &TradingData, _error: &FeedError) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called every `Strategy::set_timer` interval
+    async fn on_timer(&mut self, _data: &TradingData) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+
+    /// Called once when `run` returns - intents returned here are still executed
+    async fn on_stop(&mut self, _data: &TradingData) -> Vec<OrderIntent> {
+        Vec::new()
+    }
+}
+
+pub struct Strategy<S> {
     name: String,
-    client: AuthorizedClient,
+    execution: Box<dyn ExecutionLayer>,
+    /// `None` when messages come from somewhere else, e.g. a backtest's records
+    feed: Option<FeedConnection>,
     products: Vec<String>,
-    data: TradingData<T>, // maybe something like this??
-    strategy: fn(&AuthorizedClient, &mut TradingData<T>),
+    channels: Vec<String>,
+    sequences: SequenceTracker,
+    data: TradingData,
+    strategy: S,
+    timer: Option<Duration>,
 }
 
-impl<T> Strategy<T> {
+impl<S: TradingStrategy> Strategy<S> {
     pub fn new(
         name: &str, 
         client: AuthorizedClient,
         products: Vec<&str>, 
-        strategy: fn(&AuthorizedClient, &mut TradingData<T>),
-        user_data: T,
+        strategy: S,
+    ) -> Self {
+        // authenticated so the `user` channel can share the connection
+        let feed = client.authenticated_feed();
+        Strategy::build(name, Some(feed), Box::new(LiveExecution::new(client)), products, strategy)
+    }
+
+    /// Paper trading - live market data from `environment`, with orders filled by `exchange`
+    /// instead of the exchange. Keep a clone of `exchange` to query balances and orders while
+    /// the strategy runs.
+    pub fn paper(
+        name: &str,
+        environment: &Environment,
+        products: Vec<&str>,
+        strategy: S,
+        exchange: SimulatedExchange,
+    ) -> Self {
+        let feed = FeedConnection::new(environment.websocket_url());
+        Strategy::build(name, Some(feed), Box::new(exchange), products, strategy)
+    }
+
+    fn build(
+        name: &str,
+        feed: Option<FeedConnection>,
+        execution: Box<dyn ExecutionLayer>,
+        products: Vec<&str>,
+        strategy: S,
     ) -> Self {
         Strategy {
             name: name.to_string(),
-            client,
+            feed,
+            execution,
             products: products.iter().map(|s| s.to_string()).collect(),
-            data: TradingData{ products: HashMap::new(), user_data },
+            channels: Vec::new(),
+            sequences: SequenceTracker::monotonic(),
+            data: TradingData{ products: HashMap::new(), books: HashMap::new(), gaps: HashMap::new(), connection: ConnectionState::Disconnected { reason: "not connected".to_string() } },
             strategy,
+            timer: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn strategy(&self) -> &S {
+        &self.strategy
+    }
+
+    pub fn strategy_mut(&mut self) -> &mut S {
+        &mut self.strategy
+    }
+
+    /// Routes intents somewhere other than the live exchange, e.g. a paper trader
+    pub fn set_execution<E: ExecutionLayer + 'static>(&mut self, execution: E) {
+        self.execution = Box::new(execution);
+    }
+
+    /// Calls `TradingStrategy::on_timer` every `period` while running
+    pub fn set_timer(&mut self, period: Duration) {
+        self.timer = Some(period);
+    }
+
+    /// Fills `TradingData::products` from the REST API, so strategies have prices before the
+    /// first ticker message. Ticker messages replace the data as they arrive.
+    pub async fn load_products(&mut self, client: &PublicClient) -> Result<(), RequestError> {
+        for product_id in &self.products {
+            let ticker = client.get_ticker(product_id).await?;
+            let stats = client.get_stats(product_id).await?;
+            self.data.products.insert(product_id.to_string(), ProductData::from_rest(product_id, &ticker, &stats));
+        }
+        Ok(())
+    }
+
+    pub async fn subscribe_to_ticker_data(&mut self) {
+        self.subscribe_to_channel("ticker").await;
+    }
+
+    /// Keeps a local level 2 order book per product in `TradingData::books`
+    pub async fn subscribe_to_level2_data(&mut self) {
+        self.subscribe_to_channel("level2").await;
+    }
+
+    /// Reports fills on our own orders to `TradingStrategy::on_fill`
+    pub async fn subscribe_to_user_data(&mut self) {
+        self.subscribe_to_channel("user").await;
+    }
+
+    async fn subscribe_to_channel(&mut self, channel: &str) {
+        if !self.channels.iter().any(|c| c == channel) {
+            self.channels.push(channel.to_string());
         }
+        let feed = match self.feed.as_mut() {
+            Some(feed) => feed,
+            None => return,
+        };
+        let products: Vec<&str> = self.products.iter().map(|s| &s[..]).collect();
+        feed.subscribe(&products, &[channel]).await;
+        // a failed connect is retried with backoff by `run`
+        self.data.connection = match feed.connect().await {
+            Ok(()) => ConnectionState::Connected,
+            Err(e) => ConnectionState::Disconnected { reason: e.to_string() },
+        };
     }
 
-    pub fn subscribe_to_ticker_data(&mut self) {
-        &self.client.connect_socket();
-        &self.client.subscribe(self.products.iter().map(|s| &s[..]).collect(), vec!["ticker"]);
+    /// Drops the product's data and resubscribes its market data so the exchange sends fresh state.
+    /// The `user` channel is left alone so none of our fills are missed meanwhile.
+    async fn resync(&mut self, gap: SequenceGap) {
+        if let Some(feed) = self.feed.as_mut() {
+            let channels: Vec<&str> = self.channels.iter().map(|s| &s[..]).filter(|c| MARKET_DATA_CHANNELS.contains(c)).collect();
+            feed.unsubscribe(&[&gap.product_id], &channels).await;
+            feed.subscribe(&[&gap.product_id], &channels).await;
+        }
+        self.sequences.reset(&gap.product_id);
+        self.data.books.remove(&gap.product_id);
+        self.data.gaps.insert(gap.product_id.to_string(), gap.clone());
+        let intents = self.strategy.on_gap(&self.data, &gap).await;
+        self.execute(intents).await;
     }
 
-    pub fn run(&mut self) {
-        
+    /// Runs until the feed is closed or gives up reconnecting
+    pub async fn run(&mut self) {
+        if self.feed.is_none() {
+            return;
+        }
+        let mut timer = self.timer.map(|period| {
+            let mut timer = interval(period);
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            timer
+        });
+        self.start().await;
         loop {
-            if let Some(ticker_data) = self.client.read_from_ws().unwrap().into_text().ok() {
-                let ticker_data: Value = serde_json::from_str(&ticker_data).unwrap();
-                // println!("{}", ticker_data);
-                match &ticker_data["product_id"] {
-                    serde_json::Value::Null => continue,
-                    serde_json::Value::String(s) => {
-                        self.update_product(s.to_string(), &ticker_data);
-                    },
-                    _ => continue,
+            let event = tokio::select! {
+                event = next_event(&mut self.feed) => event,
+                _ = tick(&mut timer) => {
+                    self.timer_tick().await;
+                    continue;
+                },
+            };
+            match event {
+                Ok(event) => self.handle_event(event).await,
+                Err(FeedError::NotConnected) => break,
+                Err(e) => self.feed_error(e).await,
+            }
+        }
+        self.stop().await;
+    }
+
+    /// Runs on recorded messages instead of the feed, until every message was played. Intents go
+    /// to the execution layer as usual, so pair this with a simulated exchange.
+    pub async fn replay(&mut self, replayer: &mut Replayer) {
+        let mut timer = self.timer.map(|period| {
+            let mut timer = interval(period);
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            timer
+        });
+        self.data.connection = ConnectionState::Connected;
+        self.start().await;
+        loop {
+            let record = tokio::select! {
+                record = replayer.next_message() => record,
+                _ = tick(&mut timer) => {
+                    self.timer_tick().await;
+                    continue;
+                },
+            };
+            match record {
+                Some(record) => {
+                    if let Some(message) = record.message() {
+                        self.handle_event(FeedEvent::Message(message)).await;
+                    }
                 }
-            
-                (self.strategy)(&self.client, &mut self.data)
-                // let action_block = async {
-                //     (self.strategy)(&self.client, &self.data)
-                // };
-                // rt.block_on(action_block);
-                
+                None => break,
             }
-            // may or may not be necessary
-            thread::sleep(Duration::from_millis(1));
         }
+        self.stop().await;
+    }
+
+    async fn start(&mut self) {
+        let intents = self.strategy.on_start(&self.data).await;
+        self.execute(intents).await;
     }
 
-    fn update_product(&mut self, product: String, data: &Value) {
+    async fn stop(&mut self) {
+        let intents = self.strategy.on_stop(&self.data).await;
+        self.execute(intents).await;
+    }
+
+    async fn timer_tick(&mut self) {
+        let intents = self.strategy.on_timer(&self.data).await;
+        self.execute(intents).await;
+    }
+
+    async fn feed_error(&mut self, error: FeedError) {
+        let intents = self.strategy.on_feed_error(&self.data, &error).await;
+        self.execute(intents).await;
+    }
+
+    async fn handle_event(&mut self, event: FeedEvent) {
+        let message = match event {
+            FeedEvent::Message(message) => message,
+            FeedEvent::Connection(state) => {
+                if let ConnectionState::Disconnected { .. } = state {
+                    // replayed level2 subscriptions start with a fresh snapshot
+                    self.data.books.clear();
+                }
+                self.data.connection = state;
+                let intents = self.strategy.on_connection(&self.data, &self.data.connection).await;
+                return self.execute(intents).await;
+            },
+        };
+        // stale and rewound tickers are dropped before the execution layer sees them, so they can't
+        // move simulated prices or fill simulated orders
+        let check = match &message {
+            FeedMessage::Ticker(ticker) => self.sequences.check(&ticker.product_id, ticker.sequence),
+            _ => SequenceCheck::InOrder,
+        };
+        match check {
+            SequenceCheck::InOrder => {},
+            SequenceCheck::Stale => return,
+            SequenceCheck::Gap(gap) => return self.resync(gap).await,
+        }
+        let reports = self.execution.on_market_data(&message).await;
+        self.report(reports).await;
+        let intents = match message {
+            FeedMessage::Ticker(ticker) => {
+                let level2 = self.channels.iter().any(|c| c == "level2");
+                if !level2 || self.data.books.contains_key(&ticker.product_id) {
+                    self.data.gaps.remove(&ticker.product_id);
+                }
+                self.update_product(&ticker);
+                self.strategy.on_ticker(&self.data, &ticker).await
+            },
+            FeedMessage::Snapshot(snapshot) => {
+                self.data.books.insert(snapshot.product_id.to_string(), OrderBook::from_snapshot(&snapshot));
+                self.strategy.on_book_update(&self.data, &snapshot.product_id).await
+            },
+            FeedMessage::L2Update(update) => {
+                match self.data.books.get_mut(&update.product_id) {
+                    Some(book) => book.apply_update(&update),
+                    None => return,
+                }
+                self.strategy.on_book_update(&self.data, &update.product_id).await
+            },
+            // matches from the `user` channel carry our ids, unlike public or recorded ones
+            FeedMessage::Match(m) if m.user_id.is_some() || m.profile_id.is_some() => {
+                let fill = OrderFill::from_match(&m);
+                self.strategy.on_fill(&self.data, &fill).await
+            },
+            FeedMessage::Error(e) => {
+                let reason = match e.reason {
+                    Some(reason) => format!("{}: {}", e.message, reason),
+                    None => e.message,
+                };
+                return self.feed_error(FeedError::Rejected(reason)).await;
+            },
+            _ => return,
+        };
+        self.execute(intents).await;
+    }
+
+    /// Executes intents, and any the strategy returns in reaction to their reports
+    async fn execute(&mut self, intents: Vec<OrderIntent>) {
+        let mut queue = VecDeque::from(intents);
+        while let Some(intent) = queue.pop_front() {
+            let reports = self.execution.execute(intent).await;
+            queue.extend(self.dispatch(reports).await);
+        }
+    }
+
+    /// Passes reports the execution layer produced on its own to the strategy
+    async fn report(&mut self, reports: Vec<ExecutionReport>) {
+        let intents = self.dispatch(reports).await;
+        self.execute(intents).await;
+    }
+
+    async fn dispatch(&mut self, reports: Vec<ExecutionReport>) -> Vec<OrderIntent> {
+        let mut intents = Vec::new();
+        for report in reports {
+            intents.extend(match &report {
+                ExecutionReport::Fill(fill) => self.strategy.on_fill(&self.data, fill).await,
+                report => self.strategy.on_execution(&self.data, report).await,
+            });
+        }
+        intents
+    }
+
+    fn update_product(&mut self, ticker: &Ticker) {
+        let last_trade = match (ticker.trade_id, ticker.side, ticker.last_size) {
+            (Some(trade_id), Some(side), Some(size)) => Some(Trade {
+                trade_id,
+                price: ticker.price,
+                side,
+                size,
+            }),
+            _ => self.data.products.get(&ticker.product_id).and_then(|p| p.last_trade.clone()),
+        };
         let new_item = ProductData {
-            product_id: product.to_string(),
-            price: Decimal::from_str(data["price"].as_str().unwrap()).unwrap(), 
-            best_bid: Decimal::from_str(data["best_bid"].as_str().unwrap()).unwrap(),
-            best_ask: Decimal::from_str(data["best_ask"].as_str().unwrap()).unwrap(),
-            high_24h: Decimal::from_str(data["high_24h"].as_str().unwrap()).unwrap(),
-            low_24h: Decimal::from_str(data["low_24h"].as_str().unwrap()).unwrap(),
-            volume_24h: Decimal::from_str(data["volume_24h"].as_str().unwrap()).unwrap(),
-            volume_30d: Decimal::from_str(data["volume_30d"].as_str().unwrap()).unwrap(),
-            last_trade: Trade {
-                trade_id: data["trade_id"].as_u64().unwrap(),
-                price: Decimal::from_str(data["price"].as_str().unwrap()).unwrap(),
-                side: data["side"].as_str().unwrap().to_string(),
-                size: Decimal::from_str(data["last_size"].as_str().unwrap()).unwrap(),
-            }
+            product_id: ticker.product_id.to_string(),
+            price: ticker.price,
+            best_bid: ticker.best_bid,
+            best_ask: ticker.best_ask,
+            high_24h: ticker.high_24h,
+            low_24h: ticker.low_24h,
+            volume_24h: ticker.volume_24h,
+            volume_30d: ticker.volume_30d,
+            last_trade,
         };
-        self.data.products.insert(product, new_item);
+        self.data.products.insert(ticker.product_id.to_string(), new_item);
+    }
+}
+
+async fn next_event(feed: &mut Option<FeedConnection>) -> Result<FeedEvent, FeedError> {
+    match feed {
+        Some(feed) => feed.next_event().await,
+        None => Err(FeedError::NotConnected),
+    }
+}
+
+/// Waits for the next tick, or forever if there is no timer
+async fn tick(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        },
+        None => futures_util::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coinbase_pro_api_rust::orders::NewOrder;
+    use rust_decimal_macros::dec;
+    use simulation::Fees;
+
+    struct Idle;
+
+    impl TradingStrategy for Idle {}
+
+    fn paper(exchange: &SimulatedExchange) -> Strategy<Idle> {
+        Strategy::build("paper", None, Box::new(exchange.clone()), vec!["BTC-USD"], Idle)
+    }
+
+    /// Ticker for BTC-USD, with a trade at `price` if `trade_id` is set
+    fn ticker(sequence: u64, price: Decimal, trade_id: Option<u64>) -> FeedEvent {
+        FeedEvent::Message(FeedMessage::Ticker(Ticker {
+            sequence,
+            product_id: "BTC-USD".to_string(),
+            price,
+            open_24h: price,
+            volume_24h: dec!(1000),
+            low_24h: price,
+            high_24h: price,
+            volume_30d: dec!(30000),
+            best_bid: price - dec!(1),
+            best_ask: price + dec!(1),
+            side: trade_id.map(|_| Side::Sell),
+            time: None,
+            trade_id,
+            last_size: trade_id.map(|_| dec!(1)),
+        }))
+    }
+
+    #[tokio::test]
+    async fn channels_are_subscribed_once() {
+        let feed = FeedConnection::new("ws://127.0.0.1:1");
+        let mut strategy = Strategy::build("live", Some(feed), Box::new(SimulatedExchange::new(Fees::default())), vec!["BTC-USD"], Idle);
+        strategy.subscribe_to_ticker_data().await;
+        strategy.subscribe_to_user_data().await;
+        strategy.subscribe_to_ticker_data().await;
+        assert_eq!(strategy.channels, vec!["ticker", "user"]);
+    }
+
+    #[tokio::test]
+    async fn rewound_tickers_do_not_reach_the_simulated_exchange() {
+        let exchange = SimulatedExchange::new(Fees::default());
+        exchange.deposit("USD", dec!(1000));
+        let mut strategy = paper(&exchange);
+        strategy.handle_event(ticker(10, dec!(100), None)).await;
+        let placed = exchange.clone().execute(OrderIntent::Place(NewOrder::limit("BTC-USD", Side::Buy, dec!(95), dec!(1)))).await;
+        assert!(matches!(&placed[..], [ExecutionReport::Accepted { .. }]));
+
+        // an older ticker trading through our bid would fill it if it got through
+        strategy.handle_event(ticker(5, dec!(90), Some(1))).await;
+        assert!(exchange.trades().is_empty());
+        assert_eq!(strategy.data.products["BTC-USD"].price, dec!(100));
+        assert!(strategy.data.gaps.contains_key("BTC-USD"));
+
+        // the next ticker after resubscribing is accepted again
+        strategy.handle_event(ticker(11, dec!(90), Some(2))).await;
+        assert_eq!(exchange.trades().len(), 1);
+        assert!(strategy.data.is_synced("BTC-USD"));
     }
 }
@@ -0,0 +1,847 @@
+use crate::execution::{ExecutionLayer, ExecutionReport};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use coinbase_pro_api_rust::feed::FeedMessage;
+use coinbase_pro_api_rust::level2_feed::OrderBook;
+use coinbase_pro_api_rust::orders::{CancelAfter, Liquidity, NewOrder, OrderType, Side, TimeInForce};
+use coinbase_pro_api_rust::user_channel::OrderFill;
+use rust_decimal::prelude::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Fee rates as a fraction of the traded value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fees {
+    pub maker: Decimal,
+    pub taker: Decimal,
+}
+
+impl Default for Fees {
+    fn default() -> Self {
+        Fees { maker: Decimal::new(5, 3), taker: Decimal::new(5, 3) }
+    }
+}
+
+/// Order placed on the simulated exchange
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedOrder {
+    pub id: String,
+    pub order: NewOrder,
+    pub filled_size: Decimal,
+    /// Size resting ahead of a limit order at its price, which has to trade before it fills
+    pub queue_ahead: Decimal,
+    pub created_at: DateTime<Utc>,
+    /// When a good till time order is canceled if it hasn't filled
+    pub expires_at: Option<DateTime<Utc>>,
+    pub done_at: Option<DateTime<Utc>>,
+}
+
+impl SimulatedOrder {
+    /// Zero for market orders placed by funds, which only ever fill once
+    pub fn remaining_size(&self) -> Decimal {
+        self.order.size.map_or(Decimal::ZERO, |size| size - self.filled_size)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.done_at.is_none()
+    }
+
+    /// `open` or `done`, as the exchange reports order status
+    pub fn status(&self) -> &'static str {
+        if self.is_open() {
+            "open"
+        } else {
+            "done"
+        }
+    }
+}
+
+/// Funds in one currency - `hold` is reserved by open orders
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedBalance {
+    pub currency: String,
+    pub balance: Decimal,
+    pub hold: Decimal,
+}
+
+impl SimulatedBalance {
+    pub fn available(&self) -> Decimal {
+        self.balance - self.hold
+    }
+}
+
+/// Fill on the simulated exchange and the fee charged for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeRecord {
+    pub fill: OrderFill,
+    pub fee: Decimal,
+}
+
+#[derive(Default)]
+struct Market {
+    book: Option<OrderBook>,
+    best_bid: Option<Decimal>,
+    best_ask: Option<Decimal>,
+    last_price: Option<Decimal>,
+    last_trade_id: u64,
+}
+
+impl Market {
+    /// Price used to value holdings - the last trade, or the middle of the spread before one
+    fn mark(&self) -> Option<Decimal> {
+        self.last_price.or_else(|| Some((self.best_bid? + self.best_ask?) / Decimal::new(2, 0)))
+    }
+
+    /// Levels an order on `side` can take, best price first. Without a level 2 book the ticker's
+    /// best price is assumed to be deep enough for any order.
+    fn liquidity(&self, side: Side) -> Vec<(Decimal, Decimal)> {
+        if let Some(book) = &self.book {
+            let levels = match side {
+                Side::Buy => book.asks(usize::MAX),
+                Side::Sell => book.bids(usize::MAX),
+            };
+            if !levels.is_empty() {
+                return levels.iter().map(|level| (level.price, level.size)).collect();
+            }
+        }
+        let best = match side {
+            Side::Buy => self.best_ask,
+            Side::Sell => self.best_bid,
+        };
+        best.map(|price| vec![(price, Decimal::MAX)]).unwrap_or_default()
+    }
+}
+
+/// Splits `BTC-USD` into its base and quote currencies
+fn currencies(product_id: &str) -> (&str, &str) {
+    product_id.split_once('-').unwrap_or((product_id, ""))
+}
+
+struct Exchange {
+    fees: Fees,
+    slippage: Decimal,
+    clock: Option<DateTime<Utc>>,
+    next_order_id: u64,
+    next_trade_id: u64,
+    balances: BTreeMap<String, Decimal>,
+    markets: HashMap<String, Market>,
+    orders: Vec<SimulatedOrder>,
+    trades: Vec<TradeRecord>,
+}
+
+impl Exchange {
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.unwrap_or_else(Utc::now)
+    }
+
+    fn hold(&self, currency: &str) -> Decimal {
+        self.orders
+            .iter()
+            .filter(|order| order.is_open())
+            .map(|order| {
+                let (base, quote) = currencies(&order.order.product_id);
+                match (order.order.side, order.order.price) {
+                    (Side::Sell, _) if base == currency => order.remaining_size(),
+                    (Side::Buy, Some(price)) if quote == currency => {
+                        price * order.remaining_size() * (Decimal::ONE + self.fees.taker)
+                    },
+                    _ => Decimal::ZERO,
+                }
+            })
+            .sum()
+    }
+
+    fn balance(&self, currency: &str) -> SimulatedBalance {
+        SimulatedBalance {
+            currency: currency.to_string(),
+            balance: self.balances.get(currency).copied().unwrap_or_default(),
+            hold: self.hold(currency),
+        }
+    }
+
+    fn place(&mut self, order: NewOrder) -> Vec<ExecutionReport> {
+        let reject = |order, reason: &str| vec![ExecutionReport::Rejected { order, reason: reason.to_string() }];
+        if order.order_type == OrderType::Stop || order.stop.is_some() {
+            return reject(order, "stop orders are not supported");
+        }
+        let limit = match (order.order_type, order.price) {
+            (OrderType::Limit, Some(price)) if price > Decimal::ZERO => Some(price),
+            (OrderType::Limit, _) => return reject(order, "limit order without a price"),
+            _ => None,
+        };
+        match (order.size, order.funds) {
+            (Some(size), None) if size > Decimal::ZERO => {},
+            (None, Some(funds)) if limit.is_none() && funds > Decimal::ZERO => {},
+            _ => return reject(order, "order needs a positive size, or funds for a market order"),
+        }
+        if limit.is_none() && (order.post_only || order.time_in_force.is_some()) {
+            return reject(order, "post only and time in force are only for limit orders");
+        }
+        let time_in_force = order.time_in_force.unwrap_or(TimeInForce::GoodTillCanceled);
+        let expires_at = match (time_in_force, order.cancel_after) {
+            (TimeInForce::GoodTillTime, Some(cancel_after)) => Some(self.now() + match cancel_after {
+                CancelAfter::Min => Duration::minutes(1),
+                CancelAfter::Hour => Duration::hours(1),
+                CancelAfter::Day => Duration::days(1),
+            }),
+            (TimeInForce::GoodTillTime, None) => return reject(order, "good till time without cancel after"),
+            (_, Some(_)) => return reject(order, "cancel after without good till time"),
+            (_, None) => None,
+        };
+        let rests = limit.is_some() && matches!(time_in_force, TimeInForce::GoodTillCanceled | TimeInForce::GoodTillTime);
+        let liquidity = self
+            .markets
+            .get(&order.product_id)
+            .map(|market| market.liquidity(order.side))
+            .unwrap_or_default();
+        if limit.is_none() && liquidity.is_empty() {
+            return reject(order, "no market data for the product");
+        }
+
+        // the part of the order that crosses the book fills straight away as a taker. Orders by
+        // funds take levels until the funds, including fees for buys, are used up.
+        let mut fills = Vec::new();
+        let mut remaining = order.size;
+        let mut funds = order.funds;
+        for (price, size) in liquidity {
+            let crosses = match (order.side, limit) {
+                (_, None) => true,
+                (Side::Buy, Some(limit)) => price <= limit,
+                (Side::Sell, Some(limit)) => price >= limit,
+            };
+            if !crosses {
+                break;
+            }
+            let price = match (limit, order.side) {
+                (Some(_), _) => price,
+                (None, Side::Buy) => price * (Decimal::ONE + self.slippage),
+                (None, Side::Sell) => price * (Decimal::ONE - self.slippage),
+            };
+            let cost = match order.side {
+                Side::Buy => price * (Decimal::ONE + self.fees.taker),
+                Side::Sell => price,
+            };
+            let take = match (remaining, funds) {
+                (Some(remaining), _) => remaining.min(size),
+                (None, Some(funds)) => (funds / cost).min(size),
+                (None, None) => Decimal::ZERO,
+            };
+            if take.is_zero() {
+                break;
+            }
+            fills.push((price, take));
+            remaining = remaining.map(|remaining| remaining - take);
+            // a level deep enough for the rest of the funds leaves nothing over
+            funds = funds.map(|funds| if take < size { Decimal::ZERO } else { funds - cost * take });
+        }
+        if order.post_only && !fills.is_empty() {
+            return reject(order, "post only order would take liquidity");
+        }
+        if time_in_force == TimeInForce::FillOrKill && remaining != Some(Decimal::ZERO) {
+            return reject(order, "fill or kill order could not be filled in full");
+        }
+
+        let (base, quote) = currencies(&order.product_id);
+        let resting = if rests { remaining.unwrap_or_default() } else { Decimal::ZERO };
+        let (currency, needed) = match order.side {
+            Side::Buy => {
+                let cost: Decimal = fills.iter().map(|(price, size)| price * size).sum();
+                let resting = limit.map(|price| price * resting).unwrap_or_default();
+                (quote, (cost + resting) * (Decimal::ONE + self.fees.taker))
+            },
+            Side::Sell => (base, fills.iter().map(|(_, size)| size).sum::<Decimal>() + resting),
+        };
+        if needed > self.balance(currency).available() {
+            return reject(order, "insufficient funds");
+        }
+
+        let now = self.now();
+        let mut placed = SimulatedOrder {
+            id: format!("sim-{}", self.next_order_id),
+            order,
+            filled_size: Decimal::ZERO,
+            queue_ahead: Decimal::ZERO,
+            created_at: now,
+            expires_at,
+            done_at: None,
+        };
+        self.next_order_id += 1;
+        let mut reports = vec![ExecutionReport::Accepted { order_id: placed.id.clone(), order: placed.order.clone() }];
+        for (price, size) in fills {
+            reports.push(self.fill(&mut placed, price, size, Liquidity::Taker));
+        }
+        match limit {
+            Some(price) if rests && !placed.remaining_size().is_zero() => {
+                placed.queue_ahead = self
+                    .markets
+                    .get(&placed.order.product_id)
+                    .and_then(|market| market.book.as_ref())
+                    .map(|book| book.size_at(placed.order.side, price))
+                    .unwrap_or_default();
+            },
+            // whatever a market or immediate or cancel order couldn't fill is dropped
+            _ => placed.done_at = Some(now),
+        }
+        self.orders.push(placed);
+        reports
+    }
+
+    fn cancel(&mut self, order_id: &str) -> Vec<ExecutionReport> {
+        let now = self.now();
+        match self.orders.iter_mut().find(|order| order.id == order_id && order.is_open()) {
+            Some(order) => {
+                order.done_at = Some(now);
+                vec![ExecutionReport::Canceled { order_id: order_id.to_string() }]
+            },
+            None => vec![ExecutionReport::CancelRejected {
+                order_id: order_id.to_string(),
+                reason: "order is not open".to_string(),
+            }],
+        }
+    }
+
+    fn cancel_by_client_oid(&mut self, client_oid: &str) -> Vec<ExecutionReport> {
+        let open = self
+            .orders
+            .iter()
+            .find(|order| order.is_open() && order.order.client_oid.as_deref() == Some(client_oid));
+        match open {
+            Some(order) => {
+                let order_id = order.id.clone();
+                self.cancel(&order_id)
+            },
+            None => vec![ExecutionReport::CancelRejected {
+                order_id: client_oid.to_string(),
+                reason: "no open order with this client_oid".to_string(),
+            }],
+        }
+    }
+
+    fn cancel_all(&mut self, product_id: Option<&str>) -> Vec<ExecutionReport> {
+        let now = self.now();
+        self.orders
+            .iter_mut()
+            .filter(|order| order.is_open() && match product_id { Some(product_id) => order.order.product_id == product_id, None => true })
+            .map(|order| {
+                order.done_at = Some(now);
+                ExecutionReport::Canceled { order_id: order.id.clone() }
+            })
+            .collect()
+    }
+
+    fn fill(&mut self, order: &mut SimulatedOrder, price: Decimal, size: Decimal, liquidity: Liquidity) -> ExecutionReport {
+        let fee_rate = match liquidity {
+            Liquidity::Maker => self.fees.maker,
+            Liquidity::Taker => self.fees.taker,
+        };
+        let value = price * size;
+        let fee = value * fee_rate;
+        let (base, quote) = currencies(&order.order.product_id);
+        let (base_change, quote_change) = match order.order.side {
+            Side::Buy => (size, -value - fee),
+            Side::Sell => (-size, value - fee),
+        };
+        *self.balances.entry(base.to_string()).or_default() += base_change;
+        *self.balances.entry(quote.to_string()).or_default() += quote_change;
+
+        order.filled_size += size;
+        if order.remaining_size().is_zero() {
+            order.done_at = Some(self.now());
+        }
+        self.next_trade_id += 1;
+        let fill = OrderFill {
+            order_id: order.id.clone(),
+            trade_id: self.next_trade_id,
+            product_id: order.order.product_id.clone(),
+            side: order.order.side,
+            price,
+            size,
+            liquidity,
+            fee_rate: Some(fee_rate),
+            time: self.now(),
+        };
+        self.trades.push(TradeRecord { fill: fill.clone(), fee });
+        ExecutionReport::Fill(fill)
+    }
+
+    /// Cancels good till time orders whose time is up
+    fn expire(&mut self) -> Vec<ExecutionReport> {
+        let now = self.now();
+        self.orders
+            .iter_mut()
+            .filter(|order| order.is_open() && matches!(order.expires_at, Some(expires_at) if expires_at <= now))
+            .map(|order| {
+                order.done_at = Some(now);
+                ExecutionReport::Canceled { order_id: order.id.clone() }
+            })
+            .collect()
+    }
+
+    fn on_market_data(&mut self, message: &FeedMessage) -> Vec<ExecutionReport> {
+        let mut reports = self.expire();
+        reports.extend(match message {
+            FeedMessage::Ticker(ticker) => {
+                let market = self.markets.entry(ticker.product_id.clone()).or_default();
+                market.best_bid = Some(ticker.best_bid);
+                market.best_ask = Some(ticker.best_ask);
+                market.last_price = Some(ticker.price);
+                match (ticker.trade_id, ticker.side, ticker.last_size) {
+                    // the ticker's side is the taker's
+                    (Some(trade_id), Some(side), Some(size)) => {
+                        self.trade(&ticker.product_id, trade_id, ticker.price, size, side.opposite())
+                    },
+                    _ => Vec::new(),
+                }
+            },
+            FeedMessage::Snapshot(snapshot) => {
+                let market = self.markets.entry(snapshot.product_id.clone()).or_default();
+                market.book = Some(OrderBook::from_snapshot(snapshot));
+                self.requeue(&snapshot.product_id);
+                Vec::new()
+            },
+            FeedMessage::L2Update(update) => {
+                if let Some(book) = self.markets.get_mut(&update.product_id).and_then(|market| market.book.as_mut()) {
+                    book.apply_update(update);
+                    self.requeue(&update.product_id);
+                }
+                Vec::new()
+            },
+            FeedMessage::Match(m) => self.trade(&m.product_id, m.trade_id, m.price, m.size, m.side),
+            _ => Vec::new(),
+        });
+        reports
+    }
+
+    /// Orders can only move up the queue - shrinking levels mean orders ahead were canceled
+    fn requeue(&mut self, product_id: &str) {
+        let book = match self.markets.get(product_id).and_then(|market| market.book.as_ref()) {
+            Some(book) => book,
+            None => return,
+        };
+        for order in self.orders.iter_mut().filter(|order| order.is_open() && order.order.product_id == product_id) {
+            if let Some(price) = order.order.price {
+                order.queue_ahead = order.queue_ahead.min(book.size_at(order.order.side, price));
+            }
+        }
+    }
+
+    /// Fills resting orders on the maker's side of a trade, best priced first, up to the trade's
+    /// size. Trades through an order's price fill it straight away, while trades at its price
+    /// first use up the queue ahead of it.
+    fn trade(&mut self, product_id: &str, trade_id: u64, price: Decimal, size: Decimal, maker_side: Side) -> Vec<ExecutionReport> {
+        let market = self.markets.entry(product_id.to_string()).or_default();
+        // ticker and match messages report the same trades
+        if trade_id <= market.last_trade_id {
+            return Vec::new();
+        }
+        market.last_trade_id = trade_id;
+        market.last_price = Some(price);
+
+        let mut orders = std::mem::take(&mut self.orders);
+        let mut resting: Vec<(usize, Decimal)> = orders
+            .iter()
+            .enumerate()
+            .filter(|(_, order)| order.is_open() && order.order.product_id == product_id && order.order.side == maker_side)
+            .filter_map(|(index, order)| Some((index, order.order.price?)))
+            .collect();
+        // sorting is stable, so orders at the same price keep their time priority
+        match maker_side {
+            Side::Buy => resting.sort_by_key(|&(_, limit)| std::cmp::Reverse(limit)),
+            Side::Sell => resting.sort_by_key(|&(_, limit)| limit),
+        }
+
+        let mut reports = Vec::new();
+        let mut left = size;
+        for (index, limit) in resting {
+            let order = &mut orders[index];
+            let through = match maker_side {
+                Side::Buy => limit > price,
+                Side::Sell => limit < price,
+            };
+            let filled = if through {
+                order.remaining_size().min(left)
+            } else if limit == price {
+                let past_queue = (size - order.queue_ahead).max(Decimal::ZERO);
+                order.queue_ahead = (order.queue_ahead - size).max(Decimal::ZERO);
+                order.remaining_size().min(past_queue).min(left)
+            } else {
+                Decimal::ZERO
+            };
+            if !filled.is_zero() {
+                left -= filled;
+                reports.push(self.fill(order, limit, filled, Liquidity::Maker));
+            }
+        }
+        self.orders = orders;
+        reports
+    }
+
+    fn equity(&self, quote_currency: &str) -> Decimal {
+        self.balances
+            .iter()
+            .map(|(currency, balance)| {
+                if currency == quote_currency {
+                    return *balance;
+                }
+                let product_id = format!("{}-{}", currency, quote_currency);
+                let mark = self.markets.get(&product_id).and_then(Market::mark);
+                mark.map(|price| price * balance).unwrap_or_default()
+            })
+            .sum()
+    }
+}
+
+/// Exchange simulated from market data, shared by backtests and paper trading. Clones share the
+/// same state, so one can be handed to a `Strategy` as its execution layer and another kept to
+/// query balances, orders and trades.
+///
+/// Our own orders are not added to the simulated book, so a large order can fill against more of
+/// a level than it would have on the exchange, and `stp` never has a self-trade to prevent. Stop
+/// orders are rejected.
+#[derive(Clone)]
+pub struct SimulatedExchange {
+    state: Arc<Mutex<Exchange>>,
+}
+
+impl SimulatedExchange {
+    pub fn new(fees: Fees) -> Self {
+        SimulatedExchange {
+            state: Arc::new(Mutex::new(Exchange {
+                fees,
+                slippage: Decimal::ZERO,
+                clock: None,
+                next_order_id: 1,
+                next_trade_id: 0,
+                balances: BTreeMap::new(),
+                markets: HashMap::new(),
+                orders: Vec::new(),
+                trades: Vec::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Exchange> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn deposit(&self, currency: &str, amount: Decimal) {
+        *self.lock().balances.entry(currency.to_string()).or_default() += amount;
+    }
+
+    /// Market orders fill this fraction worse than the book's prices, e.g. `0.001` for 0.1%
+    pub fn set_slippage(&self, slippage: Decimal) {
+        self.lock().slippage = slippage;
+    }
+
+    /// Times orders and fills at `time` rather than the wall clock, e.g. during a replay
+    pub fn set_time(&self, time: DateTime<Utc>) {
+        self.lock().clock = Some(time);
+    }
+
+    pub fn balance(&self, currency: &str) -> SimulatedBalance {
+        self.lock().balance(currency)
+    }
+
+    pub fn balances(&self) -> Vec<SimulatedBalance> {
+        let exchange = self.lock();
+        exchange.balances.keys().map(|currency| exchange.balance(currency)).collect()
+    }
+
+    /// Balances per currency, like `AuthorizedClient::get_accounts`
+    pub fn get_accounts(&self) -> Vec<SimulatedBalance> {
+        self.balances()
+    }
+
+    /// Orders with any of `statuses`, like `AuthorizedClient::get_orders` - `open`, `pending` and
+    /// `active` all match open orders, `all` matches every order, and no statuses means open orders
+    pub fn get_orders(&self, statuses: &[&str]) -> Vec<SimulatedOrder> {
+        let all = statuses.contains(&"all");
+        let open = statuses.is_empty() || statuses.iter().any(|s| matches!(*s, "open" | "pending" | "active"));
+        let done = statuses.contains(&"done");
+        self.lock()
+            .orders
+            .iter()
+            .filter(|order| all || if order.is_open() { open } else { done })
+            .cloned()
+            .collect()
+    }
+
+    pub fn order(&self, order_id: &str) -> Option<SimulatedOrder> {
+        self.lock().orders.iter().find(|order| order.id == order_id).cloned()
+    }
+
+    pub fn open_orders(&self) -> Vec<SimulatedOrder> {
+        self.lock().orders.iter().filter(|order| order.is_open()).cloned().collect()
+    }
+
+    pub fn trades(&self) -> Vec<TradeRecord> {
+        self.lock().trades.clone()
+    }
+
+    /// Value of every balance in `quote_currency` at the latest prices - currencies without a
+    /// market against it count as zero
+    pub fn equity(&self, quote_currency: &str) -> Decimal {
+        self.lock().equity(quote_currency)
+    }
+}
+
+#[async_trait]
+impl ExecutionLayer for SimulatedExchange {
+    async fn place(&mut self, order: NewOrder) -> Vec<ExecutionReport> {
+        self.lock().place(order)
+    }
+
+    async fn cancel(&mut self, order_id: &str) -> Vec<ExecutionReport> {
+        self.lock().cancel(order_id)
+    }
+
+    async fn cancel_by_client_oid(&mut self, client_oid: &str) -> Vec<ExecutionReport> {
+        self.lock().cancel_by_client_oid(client_oid)
+    }
+
+    async fn cancel_all(&mut self, product_id: Option<&str>) -> Vec<ExecutionReport> {
+        self.lock().cancel_all(product_id)
+    }
+
+    async fn on_market_data(&mut self, message: &FeedMessage) -> Vec<ExecutionReport> {
+        self.lock().on_market_data(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coinbase_pro_api_rust::feed::{Match, Snapshot};
+    use coinbase_pro_api_rust::orders::StopDirection;
+    use rust_decimal_macros::dec;
+
+    fn exchange(usd: Decimal, btc: Decimal) -> SimulatedExchange {
+        let exchange = SimulatedExchange::new(Fees { maker: dec!(0.001), taker: dec!(0.002) });
+        exchange.deposit("USD", usd);
+        exchange.deposit("BTC", btc);
+        exchange
+    }
+
+    fn snapshot(bids: &[(Decimal, Decimal)], asks: &[(Decimal, Decimal)]) -> FeedMessage {
+        FeedMessage::Snapshot(Snapshot { product_id: "BTC-USD".to_string(), bids: bids.to_vec(), asks: asks.to_vec() })
+    }
+
+    /// Trade against a resting order on `maker_side`
+    fn trade(trade_id: u64, price: Decimal, size: Decimal, maker_side: Side) -> FeedMessage {
+        FeedMessage::Match(Box::new(Match {
+            trade_id,
+            sequence: trade_id,
+            maker_order_id: "maker".to_string(),
+            taker_order_id: "taker".to_string(),
+            time: Utc::now(),
+            product_id: "BTC-USD".to_string(),
+            size,
+            price,
+            side: maker_side,
+            user_id: None,
+            profile_id: None,
+            maker_user_id: None,
+            maker_profile_id: None,
+            maker_fee_rate: None,
+            taker_user_id: None,
+            taker_profile_id: None,
+            taker_fee_rate: None,
+        }))
+    }
+
+    fn fills(reports: &[ExecutionReport]) -> Vec<(Decimal, Decimal, Liquidity)> {
+        reports
+            .iter()
+            .filter_map(|report| match report {
+                ExecutionReport::Fill(fill) => Some((fill.price, fill.size, fill.liquidity)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn order_id(reports: &[ExecutionReport]) -> String {
+        match &reports[0] {
+            ExecutionReport::Accepted { order_id, .. } => order_id.clone(),
+            report => panic!("order was not accepted: {:?}", report),
+        }
+    }
+
+    #[test]
+    fn market_order_walks_the_book_as_taker() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1)), (dec!(101), dec!(2))]));
+
+        let reports = exchange.lock().place(NewOrder::market("BTC-USD", Side::Buy, dec!(2)));
+        assert_eq!(fills(&reports), vec![(dec!(100), dec!(1), Liquidity::Taker), (dec!(101), dec!(1), Liquidity::Taker)]);
+        assert_eq!(exchange.balance("BTC").balance, dec!(2));
+        assert_eq!(exchange.balance("USD").balance, dec!(1000) - dec!(201) * dec!(1.002));
+        assert!(exchange.open_orders().is_empty());
+    }
+
+    #[test]
+    fn resting_limit_order_holds_funds() {
+        let exchange = exchange(dec!(1000), dec!(1));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]));
+
+        let reports = exchange.lock().place(NewOrder::limit("BTC-USD", Side::Buy, dec!(90), dec!(2)));
+        assert!(fills(&reports).is_empty());
+        assert_eq!(exchange.balance("USD").hold, dec!(180) * dec!(1.002));
+        assert_eq!(exchange.balance("USD").balance, dec!(1000));
+
+        exchange.lock().place(NewOrder::limit("BTC-USD", Side::Sell, dec!(110), dec!(0.4)));
+        assert_eq!(exchange.balance("BTC").hold, dec!(0.4));
+        assert_eq!(exchange.balance("BTC").available(), dec!(0.6));
+    }
+
+    #[test]
+    fn rejects_orders_without_funds() {
+        let exchange = exchange(dec!(100), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]));
+
+        let reports = exchange.lock().place(NewOrder::limit("BTC-USD", Side::Buy, dec!(90), dec!(2)));
+        assert!(matches!(&reports[..], [ExecutionReport::Rejected { .. }]));
+        let reports = exchange.lock().place(NewOrder::market("BTC-USD", Side::Sell, dec!(1)));
+        assert!(matches!(&reports[..], [ExecutionReport::Rejected { .. }]));
+    }
+
+    #[test]
+    fn maker_fills_wait_for_the_queue_ahead() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(3))], &[(dec!(100), dec!(1))]));
+        let id = order_id(&exchange.lock().place(NewOrder::limit("BTC-USD", Side::Buy, dec!(99), dec!(1))));
+        assert_eq!(exchange.order(&id).unwrap().queue_ahead, dec!(3));
+
+        assert!(fills(&exchange.lock().on_market_data(&trade(1, dec!(99), dec!(2), Side::Buy))).is_empty());
+        assert_eq!(exchange.order(&id).unwrap().queue_ahead, dec!(1));
+
+        let reports = exchange.lock().on_market_data(&trade(2, dec!(99), dec!(1.5), Side::Buy));
+        assert_eq!(fills(&reports), vec![(dec!(99), dec!(0.5), Liquidity::Maker)]);
+
+        // the same trade reported twice only fills once
+        assert!(fills(&exchange.lock().on_market_data(&trade(2, dec!(99), dec!(1.5), Side::Buy))).is_empty());
+        assert_eq!(exchange.order(&id).unwrap().remaining_size(), dec!(0.5));
+    }
+
+    #[test]
+    fn trades_through_the_price_fill_at_most_the_trade_size() {
+        let exchange = exchange(dec!(100000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(90), dec!(1))], &[(dec!(100), dec!(1))]));
+        let worse = order_id(&exchange.lock().place(NewOrder::limit("BTC-USD", Side::Buy, dec!(95), dec!(100))));
+        let better = order_id(&exchange.lock().place(NewOrder::limit("BTC-USD", Side::Buy, dec!(96), dec!(0.6))));
+
+        let reports = exchange.lock().on_market_data(&trade(1, dec!(94), dec!(1), Side::Buy));
+        assert_eq!(fills(&reports), vec![(dec!(96), dec!(0.6), Liquidity::Maker), (dec!(95), dec!(0.4), Liquidity::Maker)]);
+        assert!(!exchange.order(&better).unwrap().is_open());
+        assert_eq!(exchange.order(&worse).unwrap().remaining_size(), dec!(99.6));
+        assert_eq!(exchange.balance("BTC").balance, dec!(1));
+    }
+
+    #[test]
+    fn canceled_orders_release_their_hold() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]));
+        let id = order_id(&exchange.lock().place(NewOrder::limit("BTC-USD", Side::Buy, dec!(90), dec!(2))));
+
+        assert!(matches!(&exchange.lock().cancel(&id)[..], [ExecutionReport::Canceled { .. }]));
+        assert_eq!(exchange.balance("USD").hold, dec!(0));
+        assert!(matches!(&exchange.lock().cancel(&id)[..], [ExecutionReport::CancelRejected { .. }]));
+    }
+
+    #[test]
+    fn post_only_orders_never_take() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]));
+
+        let crossing = NewOrder::limit("BTC-USD", Side::Buy, dec!(100), dec!(1)).post_only();
+        assert!(matches!(&exchange.lock().place(crossing)[..], [ExecutionReport::Rejected { .. }]));
+        let resting = NewOrder::limit("BTC-USD", Side::Buy, dec!(99.5), dec!(1)).post_only();
+        let id = order_id(&exchange.lock().place(resting));
+        assert!(exchange.order(&id).unwrap().is_open());
+    }
+
+    #[test]
+    fn immediate_or_cancel_drops_what_does_not_fill() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1)), (dec!(101), dec!(2))]));
+
+        let order = NewOrder::limit("BTC-USD", Side::Buy, dec!(100), dec!(3)).time_in_force(TimeInForce::ImmediateOrCancel);
+        let reports = exchange.lock().place(order);
+        assert_eq!(fills(&reports), vec![(dec!(100), dec!(1), Liquidity::Taker)]);
+        assert!(exchange.open_orders().is_empty());
+        assert_eq!(exchange.balance("USD").hold, dec!(0));
+    }
+
+    #[test]
+    fn fill_or_kill_fills_in_full_or_not_at_all() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1)), (dec!(101), dec!(2))]));
+
+        let order = NewOrder::limit("BTC-USD", Side::Buy, dec!(100), dec!(3)).time_in_force(TimeInForce::FillOrKill);
+        assert!(matches!(&exchange.lock().place(order)[..], [ExecutionReport::Rejected { .. }]));
+        assert_eq!(exchange.balance("BTC").balance, dec!(0));
+
+        let order = NewOrder::limit("BTC-USD", Side::Buy, dec!(101), dec!(3)).time_in_force(TimeInForce::FillOrKill);
+        let reports = exchange.lock().place(order);
+        assert_eq!(fills(&reports), vec![(dec!(100), dec!(1), Liquidity::Taker), (dec!(101), dec!(2), Liquidity::Taker)]);
+    }
+
+    #[test]
+    fn market_order_by_funds_spends_the_funds_and_fees() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1)), (dec!(101), dec!(2))]));
+
+        let reports = exchange.lock().place(NewOrder::market_funds("BTC-USD", Side::Buy, dec!(150.3)));
+        let fills = fills(&reports);
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0], (dec!(100), dec!(1), Liquidity::Taker));
+        assert!((exchange.balance("USD").balance - dec!(849.7)).abs() < dec!(0.000001));
+        assert!(exchange.open_orders().is_empty());
+    }
+
+    #[test]
+    fn rejects_stop_orders() {
+        let exchange = exchange(dec!(1000), dec!(1));
+        let order = NewOrder::limit("BTC-USD", Side::Sell, dec!(95), dec!(1)).stop(StopDirection::Loss, dec!(96));
+        assert!(matches!(&exchange.lock().place(order)[..], [ExecutionReport::Rejected { .. }]));
+    }
+
+    #[test]
+    fn good_till_time_orders_expire() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        let start = Utc::now();
+        exchange.set_time(start);
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]));
+        let id = order_id(&exchange.lock().place(NewOrder::limit("BTC-USD", Side::Buy, dec!(90), dec!(1)).cancel_after(CancelAfter::Min)));
+
+        exchange.set_time(start + Duration::seconds(30));
+        assert!(exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))])).is_empty());
+        exchange.set_time(start + Duration::seconds(61));
+        let reports = exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]));
+        assert_eq!(reports, vec![ExecutionReport::Canceled { order_id: id }]);
+        assert_eq!(exchange.balance("USD").hold, dec!(0));
+    }
+
+    #[test]
+    fn cancels_by_client_oid() {
+        let exchange = exchange(dec!(1000), dec!(0));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]));
+        let order = NewOrder::limit("BTC-USD", Side::Buy, dec!(90), dec!(1)).client_oid("my-order");
+        let id = order_id(&exchange.lock().place(order));
+
+        assert_eq!(exchange.lock().cancel_by_client_oid("my-order"), vec![ExecutionReport::Canceled { order_id: id }]);
+        assert!(matches!(&exchange.lock().cancel_by_client_oid("my-order")[..], [ExecutionReport::CancelRejected { .. }]));
+    }
+
+    #[test]
+    fn cancel_all_can_be_limited_to_a_product() {
+        let exchange = exchange(dec!(1000), dec!(10));
+        exchange.lock().on_market_data(&snapshot(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]));
+        let btc = order_id(&exchange.lock().place(NewOrder::limit("BTC-USD", Side::Buy, dec!(90), dec!(1))));
+        let eth = order_id(&exchange.lock().place(NewOrder::limit("ETH-BTC", Side::Buy, dec!(0.05), dec!(1))));
+
+        assert_eq!(exchange.lock().cancel_all(Some("BTC-USD")), vec![ExecutionReport::Canceled { order_id: btc }]);
+        assert_eq!(exchange.open_orders().len(), 1);
+        assert_eq!(exchange.lock().cancel_all(None), vec![ExecutionReport::Canceled { order_id: eth }]);
+        assert!(exchange.lock().cancel_all(None).is_empty());
+    }
+}
@@ -1,14 +1,221 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 ///
 /// Account struct - describes client accounts
 ///
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Account {
     id: String,
     currency: String,
-    balance: String,
-    available: String,
-    hold: String,
+    balance: Decimal,
+    available: Decimal,
+    hold: Decimal,
     profile_id: String,
     trading_enabled: bool,
 }
+
+impl Account {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    ///
+    /// Total funds - `available` plus `hold`
+    ///
+    pub fn balance(&self) -> Decimal {
+        self.balance
+    }
+
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    ///
+    /// Funds reserved for open orders and pending withdrawals
+    ///
+    pub fn hold(&self) -> Decimal {
+        self.hold
+    }
+
+    pub fn profile_id(&self) -> &str {
+        &self.profile_id
+    }
+
+    pub fn trading_enabled(&self) -> bool {
+        self.trading_enabled
+    }
+}
+
+///
+/// What changed an account's balance
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LedgerKind {
+    /// Deposit or withdrawal
+    Transfer,
+    /// Trade
+    Match,
+    Fee,
+    Rebate,
+    Conversion,
+    /// Kinds added by the exchange since this was written
+    #[serde(other)]
+    Other,
+}
+
+///
+/// Where a ledger entry came from - which fields are set depends on its kind
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LedgerDetails {
+    order_id: Option<String>,
+    trade_id: Option<String>,
+    product_id: Option<String>,
+    transfer_id: Option<String>,
+    transfer_type: Option<String>,
+    conversion_id: Option<String>,
+}
+
+impl LedgerDetails {
+    ///
+    /// Set for matches and fees
+    ///
+    pub fn order_id(&self) -> Option<&str> {
+        self.order_id.as_deref()
+    }
+
+    pub fn trade_id(&self) -> Option<&str> {
+        self.trade_id.as_deref()
+    }
+
+    pub fn product_id(&self) -> Option<&str> {
+        self.product_id.as_deref()
+    }
+
+    ///
+    /// Set for transfers
+    ///
+    pub fn transfer_id(&self) -> Option<&str> {
+        self.transfer_id.as_deref()
+    }
+
+    ///
+    /// `deposit` or `withdraw`
+    ///
+    pub fn transfer_type(&self) -> Option<&str> {
+        self.transfer_type.as_deref()
+    }
+
+    pub fn conversion_id(&self) -> Option<&str> {
+        self.conversion_id.as_deref()
+    }
+}
+
+///
+/// One change to an account's balance
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    id: String,
+    created_at: DateTime<Utc>,
+    amount: Decimal,
+    balance: Decimal,
+    #[serde(rename = "type")]
+    kind: LedgerKind,
+    #[serde(default)]
+    details: LedgerDetails,
+}
+
+impl LedgerEntry {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    ///
+    /// Change to the balance - negative for money leaving the account
+    ///
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    ///
+    /// Balance after the change
+    ///
+    pub fn balance(&self) -> Decimal {
+        self.balance
+    }
+
+    pub fn kind(&self) -> LedgerKind {
+        self.kind
+    }
+
+    pub fn details(&self) -> &LedgerDetails {
+        &self.details
+    }
+}
+
+///
+/// What funds are held for
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HoldKind {
+    Order,
+    Transfer,
+    #[serde(other)]
+    Other,
+}
+
+///
+/// Funds held back from an account's available balance
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hold {
+    id: String,
+    created_at: DateTime<Utc>,
+    updated_at: Option<DateTime<Utc>>,
+    amount: Decimal,
+    #[serde(rename = "type")]
+    kind: HoldKind,
+    #[serde(rename = "ref")]
+    reference: String,
+}
+
+impl Hold {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.updated_at
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn kind(&self) -> HoldKind {
+        self.kind
+    }
+
+    ///
+    /// Id of the order or transfer holding the funds
+    ///
+    pub fn reference(&self) -> &str {
+        &self.reference
+    }
+}
@@ -1,9 +1,9 @@
 extern crate base64;
 extern crate crypto;
 extern crate data_encoding;
-use coinbase_pro_api_rust::client::AuthorizedClient;
-use coinbase_pro_api_rust::level2_feed::SocketQuery;
-use coinbase_pro_api_rust::orders::Order;
+// use coinbase_pro_api_rust::client::AuthorizedClient;
+// use coinbase_pro_api_rust::level2_feed::SocketQuery;
+// use coinbase_pro_api_rust::orders::{NewOrder, Side};
 
 #[tokio::main]
 async fn main() {
@@ -14,17 +14,16 @@ async fn main() {
     // println!("{:?}", user.get_accounts().await);
     // println!(
     //     "{:?}",
-    //     user.place_order(Order::MarketOrder, "buy", "BTC-USD", None, "0.001")
+    //     user.place_order(&NewOrder::market("BTC-USD", Side::Buy, Decimal::new(1, 3)))
     //         .await
     // );
-    // // user.connect_socket();
-    // // user.subscribe(vec!["BTC-USD"], vec!["heartbeat"]);
-    // let mut sq = SocketQuery::new(user);
-    // sq.open_level2(vec!["BTC-USD"]);
+    // let mut sq = SocketQuery::new(user.feed());
+    // sq.open_level2(vec!["BTC-USD"]).await.ok();
 
     // loop {
-    //     if let Some(msg) = sq.get_order_book() {
-    //         println!("{}", msg.price);
+    //     if let Ok(Some(product_id)) = sq.update_order_book().await {
+    //         let book = sq.get_order_book(&product_id).unwrap();
+    //         println!("{:?} {:?}", book.best_bid(), book.best_ask());
     //     }
     // }
 }
@@ -0,0 +1,48 @@
+use coinbase_pro_api_rust::client::PublicClient;
+use coinbase_pro_api_rust::environment::Environment;
+use coinbase_pro_api_rust::recorder::Recorder;
+use std::env;
+use std::process;
+
+const USAGE: &str =
+    "usage: recorder <directory> <product ids, e.g. BTC-USD,ETH-USD> [channels] [--sandbox]";
+const DEFAULT_CHANNELS: &str = "heartbeat,ticker,level2,matches";
+
+///
+/// Records feed messages to disk until interrupted with ctrl-c
+///
+#[tokio::main]
+async fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let sandbox = args.iter().any(|arg| arg == "--sandbox");
+    args.retain(|arg| arg != "--sandbox");
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    let environment = if sandbox {
+        Environment::Sandbox
+    } else {
+        Environment::Production
+    };
+    let product_ids: Vec<&str> = args[1].split(',').collect();
+    let channels: Vec<&str> = args
+        .get(2)
+        .map_or(DEFAULT_CHANNELS, |s| s.as_str())
+        .split(',')
+        .collect();
+
+    let client = PublicClient::with_environment(environment);
+    let mut recorder = Recorder::new(client.feed(), &args[0]);
+    let result = match recorder.start(&product_ids, &channels).await {
+        Ok(()) => tokio::select! {
+            result = recorder.run() => result,
+            _ = tokio::signal::ctrl_c() => Ok(()),
+        },
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result.and(recorder.close().await) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,59 @@
+use coinbase_pro_api_rust::recorder::{find_records, load_records};
+use coinbase_pro_api_rust::replay::{PlaybackSpeed, ReplayServer};
+use std::env;
+use std::process;
+use tokio::net::TcpListener;
+
+const USAGE: &str =
+    "usage: replayer <directory> [speed: realtime, max or a factor such as 100] [port]";
+
+///
+/// Serves recorded feed messages on a local websocket
+///
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args.len() > 3 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    let speed = match args.get(1).map(|s| s.trim_end_matches('x')) {
+        None | Some("realtime") => PlaybackSpeed::RealTime,
+        Some("max") => PlaybackSpeed::AsFastAsPossible,
+        Some(factor) => match factor.parse() {
+            Ok(factor) => PlaybackSpeed::Accelerated(factor),
+            Err(_) => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        },
+    };
+    let port = args.get(2).map_or("8080", |s| s.as_str());
+
+    let records = match find_records(&args[0])
+        .map_err(|e| e.to_string())
+        .and_then(|paths| load_records(&paths).map_err(|e| e.to_string()))
+    {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let listener = match TcpListener::bind(format!("127.0.0.1:{}", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    println!(
+        "replaying {} messages on ws://127.0.0.1:{}",
+        records.len(),
+        port
+    );
+    if let Err(e) = ReplayServer::new(records, speed).serve(listener).await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
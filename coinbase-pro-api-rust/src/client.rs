@@ -1,26 +1,44 @@
-use crate::accounts::Account;
+use crate::accounts::{Account, Hold, LedgerEntry};
 use crate::conversion::{Conversion, ConversionResponse};
-use crate::errors::RequestError;
-use crate::orders::{LimitOrder, MarketOrder, OpenOrder, Order, OrderResponse};
-use crypto::hmac::Hmac;
-use crypto::mac::Mac;
-use crypto::sha2::Sha256;
-use native_tls::TlsStream;
-use reqwest::{Client, RequestBuilder};
+use crate::credentials::Credentials;
+use crate::environment::Environment;
+use crate::errors::{CredentialsError, ErrorKind, InvalidOrderReason, RequestError};
+use crate::fills::{Fill, FillFilter};
+use crate::level3_feed::Level3Snapshot;
+use crate::market_data::{
+    candle_ranges, merge_candles, BookLevel, BookSnapshot, Candle, Currency, Granularity,
+    ProductStats, ProductTicker, ProductTrade, ServerTime,
+};
+use crate::orders::{CanceledOrder, NewOrder, OpenOrder, OrderResponse};
+use crate::pagination::{paginate, Page, PageStream, Pagination};
+use crate::products::Product;
+use crate::rate_limit::RateLimiter;
+use crate::websocket::FeedConnection;
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, RequestBuilder, StatusCode};
 use rust_decimal::prelude::{Decimal, FromStr};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use serde_json::json;
 use std::collections::HashMap;
-use std::io::ErrorKind;
-use std::net::TcpStream;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tungstenite::{connect, stream::Stream, Message, Result as TungsteniteResult, WebSocket};
-use url::Url;
+use std::ops::Deref;
+use std::sync::Mutex;
+use tokio::time::sleep;
+
+///
+/// Requests per second the exchange allows on public and private endpoints
+///
+const PUBLIC_RATE_LIMIT: u32 = 3;
+const PRIVATE_RATE_LIMIT: u32 = 5;
+
+///
+/// Times a rate limited request is retried before giving up
+///
+const RATE_LIMIT_RETRIES: u32 = 3;
 
 ///
 /// Empty request body
-/// 
+///
 #[derive(Serialize)]
 struct MtBody {}
 
@@ -31,139 +49,199 @@ impl MtBody {
 }
 
 ///
-/// Manages user credentials and requests
+/// Whether a request can be sent again without repeating its effect. A `POST` only
+/// is when it carries a `client_oid`, which the exchange uses to reject a duplicate.
 ///
-pub struct AuthorizedClient {
-    url: String,
-    access_key: String,
-    passphrase: String,
-    secret: String,
+fn is_idempotent(method: &str, body: &str) -> bool {
+    method != "POST"
+        || serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|body| body.get("client_oid").map(|oid| !oid.is_null()))
+            .unwrap_or(false)
+}
+
+///
+/// Market data and the websocket feed - needs no API keys
+///
+pub struct PublicClient {
+    environment: Environment,
     client: Client,
-    socket: Option<WebSocket<Stream<TcpStream, TlsStream<TcpStream>>>>,
+    limiter: RateLimiter,
+    products: Mutex<HashMap<String, Product>>,
 }
 
-impl AuthorizedClient {
-    pub fn new(url: &str, access_key: &str, passphrase: &str, secret: &str) -> AuthorizedClient {
-        AuthorizedClient {
-            url: url.to_string(),
-            access_key: access_key.to_string(),
-            passphrase: passphrase.to_string(),
-            secret: secret.to_string(),
+impl PublicClient {
+    ///
+    /// Creates a client for a REST base URL - the websocket feed is picked to match it,
+    /// see `Environment::from_rest_url`
+    ///
+    pub fn new(url: &str) -> PublicClient {
+        PublicClient::with_environment(Environment::from_rest_url(url))
+    }
+
+    pub fn with_environment(environment: Environment) -> PublicClient {
+        PublicClient {
+            environment,
             client: reqwest::Client::new(),
-            socket: None,
+            limiter: RateLimiter::new(PUBLIC_RATE_LIMIT),
+            products: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn is_connected(&self) -> bool {
-        self.socket.is_none()
+    pub fn environment(&self) -> &Environment {
+        &self.environment
     }
 
     ///
-    /// Performs currency conversion
+    /// Gets every product and refreshes the cached trading rules
     ///
-    pub async fn convert(
-        &self,
-        from: &str,
-        to: &str,
-        amount: &str,
-    ) -> Result<ConversionResponse, RequestError> {
-        let conversion = Conversion::new(
-            from,
-            to,
-            Decimal::from_str(amount)
-                .or_else(|_| Err(RequestError::InvalidRequest("invalid amount".to_string())))?,
-        );
-        self.make_request("POST", "/conversions", &conversion).await
+    pub async fn get_products(&self) -> Result<Vec<Product>, RequestError> {
+        let products: Vec<Product> = self.make_request("GET", "/products", MtBody::new()).await?;
+        let mut cache = self.products.lock().unwrap();
+        for product in &products {
+            cache.insert(product.id.clone(), product.clone());
+        }
+        Ok(products)
     }
+
     ///
-    /// Gets existing orders - by id
+    /// Gets one product and refreshes its cached trading rules
     ///
-    pub async fn get_order(&self, id: &str) -> Result<OpenOrder, RequestError> {
-        let method = format!("/orders/{}", id);
-        self.make_request("GET", &method, MtBody::new()).await
+    pub async fn get_product(&self, product_id: &str) -> Result<Product, RequestError> {
+        let method = format!("/products/{}", product_id);
+        let product: Product = self.make_request("GET", &method, MtBody::new()).await?;
+        self.products
+            .lock()
+            .unwrap()
+            .insert(product.id.clone(), product.clone());
+        Ok(product)
     }
 
     ///
-    /// Gets existing orders - limiting query to orders with given status
+    /// Cached product, fetched the first time it's needed
     ///
-    pub async fn get_orders(&self, statuses: &[&str]) -> Result<Vec<OpenOrder>, RequestError> {
-        let method_and_queries = self.form_method_and_queries("/orders", statuses);
-        self.make_request("GET", &method_and_queries, MtBody::new())
-            .await
+    async fn product(&self, product_id: &str) -> Result<Product, RequestError> {
+        let cached = self.products.lock().unwrap().get(product_id).cloned();
+        match cached {
+            Some(product) => Ok(product),
+            None => self
+                .get_product(product_id)
+                .await
+                .map_err(|e| match e.kind() {
+                    ErrorKind::NotFound | ErrorKind::BadRequest => RequestError::InvalidOrder(
+                        InvalidOrderReason::UnknownProduct(product_id.to_string()),
+                    ),
+                    _ => e,
+                }),
+        }
     }
 
-    fn form_method_and_queries(&self, method: &str, queries: &[&str]) -> String {
-        let mut method_and_queries = method.to_string();
-        let mut iter = queries.iter();
-        if let Some(status) = iter.next() {
-            method_and_queries.push_str("?status=");
-            method_and_queries.push_str(status);
-            while let Some(status) = iter.next() {
-                method_and_queries.push_str("&status=");
-                method_and_queries.push_str(status);
-            }
-        }
-        method_and_queries
+    ///
+    /// Gets the full order-by-order book for a product
+    ///
+    pub async fn get_level3_book(&self, product_id: &str) -> Result<Level3Snapshot, RequestError> {
+        let method = format!("/products/{}/book?level=3", product_id);
+        self.make_request("GET", &method, MtBody::new()).await
     }
 
     ///
-    /// Places user order - supports both market and limit orders
+    /// Gets historical candles between `start` and `end`, oldest first. Long ranges are split
+    /// into several requests, as the exchange returns at most 300 candles per request.
     ///
-    pub async fn place_order(
+    pub async fn get_candles(
         &self,
-        r#type: Order,
-        side: &str,
         product_id: &str,
-        price: Option<&str>,
-        size: &str,
-    ) -> Result<OrderResponse, RequestError> {
-        match r#type {
-            Order::MarketOrder => {
-                let order = MarketOrder::new(
-                    "market".to_string(),
-                    Decimal::from_str(size).or_else(|_| {
-                        Err(RequestError::InvalidRequest("invalid size".to_string()))
-                    })?,
-                    side.to_string(),
-                    product_id.to_string(),
-                );
-                self.make_request("POST", "/orders", &order).await
-            }
-            Order::LimitOrder => {
-                let order = LimitOrder::new(
-                    "limit".to_string(),
-                    Decimal::from_str(price.ok_or(RequestError::InvalidRequest(
-                        "invalid price for limit order".to_string(),
-                    ))?)
-                    .or_else(|e| Err(RequestError::InvalidRequest("invalid price".to_string())))?,
-                    Decimal::from_str(size).or_else(|_| {
-                        Err(RequestError::InvalidRequest("invalid size".to_string()))
-                    })?,
-                    side.to_string(),
-                    product_id.to_string(),
-                );
-                self.make_request("POST", "/orders", &order).await
-            }
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        granularity: Granularity,
+    ) -> Result<Vec<Candle>, RequestError> {
+        let mut candles = Vec::new();
+        for (range_start, range_end) in candle_ranges(start, end, granularity) {
+            let method = format!(
+                "/products/{}/candles?start={}&end={}&granularity={}",
+                product_id,
+                range_start.to_rfc3339_opts(SecondsFormat::Secs, true),
+                range_end.to_rfc3339_opts(SecondsFormat::Secs, true),
+                granularity.seconds()
+            );
+            let range: Vec<Candle> = self.make_request("GET", &method, MtBody::new()).await?;
+            candles.extend(range);
         }
+        Ok(merge_candles(start, end, candles))
     }
 
     ///
-    /// Retrieves user account information
+    /// Gets the latest trade and best bid/ask for a product
     ///
-    pub async fn get_accounts(&self) -> Result<Vec<Account>, RequestError> {
-        self.make_request("GET", "/accounts", MtBody::new()).await
+    pub async fn get_ticker(&self, product_id: &str) -> Result<ProductTicker, RequestError> {
+        let method = format!("/products/{}/ticker", product_id);
+        self.make_request("GET", &method, MtBody::new()).await
+    }
+
+    ///
+    /// Gets a page of a product's trades, newest first
+    ///
+    pub async fn get_trades(
+        &self,
+        product_id: &str,
+        pagination: &Pagination,
+    ) -> Result<Page<ProductTrade>, RequestError> {
+        let method = pagination.apply(&format!("/products/{}/trades", product_id));
+        self.make_paged_request(&method).await
+    }
+
+    ///
+    /// Every trade of a product, newest first, fetched a page at a time
+    ///
+    pub fn stream_trades(
+        &self,
+        product_id: &str,
+        pagination: Pagination,
+    ) -> PageStream<'_, ProductTrade> {
+        let path = format!("/products/{}/trades", product_id);
+        paginate(pagination, move |pagination| {
+            let path = pagination.apply(&path);
+            async move { self.make_paged_request(&path).await }
+        })
+    }
+
+    ///
+    /// Gets a product's 24 hour statistics
+    ///
+    pub async fn get_stats(&self, product_id: &str) -> Result<ProductStats, RequestError> {
+        let method = format!("/products/{}/stats", product_id);
+        self.make_request("GET", &method, MtBody::new()).await
+    }
+
+    ///
+    /// Gets the best bid and ask, or every aggregated price level
+    ///
+    pub async fn get_book(
+        &self,
+        product_id: &str,
+        level: BookLevel,
+    ) -> Result<BookSnapshot, RequestError> {
+        let method = format!("/products/{}/book?level={}", product_id, level.level());
+        self.make_request("GET", &method, MtBody::new()).await
+    }
+
+    pub async fn get_currencies(&self) -> Result<Vec<Currency>, RequestError> {
+        self.make_request("GET", "/currencies", MtBody::new()).await
+    }
+
+    pub async fn get_time(&self) -> Result<ServerTime, RequestError> {
+        self.make_request("GET", "/time", MtBody::new()).await
     }
 
     ///
     /// Parses HTTP request error messages
     ///
     fn parse_request_error(&self, text: &str) -> Option<String> {
-        let message: Result<HashMap<String, String>, serde_json::Error> =
-            serde_json::from_str(text);
-        match message {
-            Ok(m) => Some(m.get("message").unwrap().to_string()),
-            Err(_) => None,
-        }
+        let error: serde_json::Value = serde_json::from_str(text).ok()?;
+        error
+            .get("message")
+            .and_then(|message| message.as_str())
+            .map(|message| message.to_string())
     }
     ///
     /// Makes HTTP request - serializes/deserializes data structures
@@ -174,171 +252,164 @@ impl AuthorizedClient {
         path: &str,
         body: impl Serialize,
     ) -> Result<T, RequestError> {
-        let mut body_text = serde_json::to_string(&body).or_else(|_| {
-            Err(RequestError::InvalidRequest(
-                "request couldn't be serialized".to_string(),
-            ))
+        let (response_text, _headers) = self
+            .send_request(method, path, body, &self.limiter, None)
+            .await?;
+        self.parse_response(&response_text)
+    }
+
+    ///
+    /// Makes a GET request to a paginated endpoint - the cursors come back in headers
+    ///
+    async fn make_paged_request<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<Page<T>, RequestError> {
+        let (response_text, headers) = self
+            .send_request("GET", path, MtBody::new(), &self.limiter, None)
+            .await?;
+        self.parse_page(&response_text, &headers)
+    }
+
+    ///
+    /// Sends a request within the rate limits, retrying if the exchange still says we're over.
+    /// Requests are signed when given credentials.
+    ///
+    async fn send_request(
+        &self,
+        method: &str,
+        path: &str,
+        body: impl Serialize,
+        limiter: &RateLimiter,
+        credentials: Option<&Credentials>,
+    ) -> Result<(String, HeaderMap), RequestError> {
+        let mut body_text = serde_json::to_string(&body).map_err(|_| {
+            RequestError::InvalidRequest("request couldn't be serialized".to_string())
         })?;
 
         if body_text == "{}" {
             body_text = "".to_string();
         }
-        let response_text = self
-            .form_request(method, path, &body_text)
-            .ok_or(RequestError::InternalError(
-                "couldn't form request".to_string(),
-            ))?
-            .send()
-            .await
-            .or_else(|_| Err(RequestError::NetworkError))?
-            .text()
-            .await
-            .or_else(|_| {
-                Err(RequestError::InternalError(
-                    "couldn't convert response to raw text".to_string(),
-                ))
-            })?;
-        let response: T = serde_json::from_str(&response_text).or_else(|_| {
-            Err(RequestError::InvalidRequest(
-                self.parse_request_error(&response_text)
-                    .ok_or(RequestError::InternalError(
-                        "couldn't deserialize response".to_string(),
-                    ))?,
-            ))
+        let mut attempt = 0;
+        let response = loop {
+            limiter.acquire().await;
+            let response = self
+                .form_request(method, path, &body_text, credentials)
+                .ok_or(RequestError::InternalError(
+                    "couldn't form request".to_string(),
+                ))?
+                .send()
+                .await
+                .map_err(|e| RequestError::NetworkError {
+                    endpoint: format!("{} {}", method, path),
+                    message: e.to_string(),
+                    timeout: e.is_timeout(),
+                    idempotent: is_idempotent(method, &body_text),
+                })?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt >= RATE_LIMIT_RETRIES {
+                break response;
+            }
+            attempt += 1;
+            sleep(std::time::Duration::from_secs(attempt as u64)).await;
+        };
+        let status = response.status();
+        let headers = response.headers().clone();
+        let response_text = response.text().await.map_err(|_| {
+            RequestError::InternalError("couldn't convert response to raw text".to_string())
         })?;
-        Ok(response)
+        if !status.is_success() {
+            return Err(RequestError::ExchangeError {
+                status: status.as_u16(),
+                message: self.parse_request_error(&response_text),
+                endpoint: format!("{} {}", method, path),
+            });
+        }
+        Ok((response_text, headers))
+    }
+
+    fn parse_response<T: DeserializeOwned>(&self, response_text: &str) -> Result<T, RequestError> {
+        serde_json::from_str(response_text).map_err(|e| {
+            RequestError::InternalError(format!("couldn't deserialize response: {}", e))
+        })
+    }
+
+    fn parse_page<T: DeserializeOwned>(
+        &self,
+        response_text: &str,
+        headers: &HeaderMap,
+    ) -> Result<Page<T>, RequestError> {
+        let cursor = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        Ok(Page {
+            items: self.parse_response(response_text)?,
+            before: cursor("CB-BEFORE"),
+            after: cursor("CB-AFTER"),
+        })
     }
 
     ///
-    /// Forms HTTP request - using user-provided API key/passcode/etc
+    /// Forms HTTP request - signed with the user-provided API key/passcode/etc if there is one
     ///
-    fn form_request(&self, method: &str, path: &str, body: &str) -> Option<RequestBuilder> {
-        let time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let signature = self.encode_signature(&time, method, path, body);
-        let headers = self.make_request_headers(time, &signature);
+    fn form_request(
+        &self,
+        method: &str,
+        path: &str,
+        body: &str,
+        credentials: Option<&Credentials>,
+    ) -> Option<RequestBuilder> {
+        let headers = match credentials {
+            Some(credentials) => self.make_request_headers(credentials, method, path, body)?,
+            None => self.make_public_headers(),
+        };
         match method {
             "GET" => Some(
                 self.client
-                    .get(self.url.to_string() + path)
+                    .get(self.environment.rest_url().to_string() + path)
                     .headers(headers),
             ),
             "POST" => Some(
                 self.client
-                    .post(self.url.to_string() + path)
+                    .post(self.environment.rest_url().to_string() + path)
                     .headers(headers)
                     .body(body.to_string()),
             ),
+            "DELETE" => Some(
+                self.client
+                    .delete(self.environment.rest_url().to_string() + path)
+                    .headers(headers),
+            ),
             _ => None,
         }
     }
 
     //// websocket api
 
-    /// This subscribes to channel(s) just like subscribe(), but does so as an authenticated user,
-    /// which gives data about your own orders and allows for more requests/second.
-    /// This isn't working for some reason, but it should. The socket says "API key invalid" even
-    /// thought it isn't...
-    pub fn authenticated_subscribe(&mut self, product_ids: Vec<&str>, channels: Vec<&str>) {
-        let time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let sig = self.encode_signature(&time, "GET", "/accounts", "");
-        if let Some(s) = self.socket.as_mut() {
-            let request = json!({
-                "type": "subscribe",
-                "product_ids": product_ids,
-                "channels": channels,
-                "signature": sig,
-                "key": self.access_key,
-                "passphrase": self.passphrase,
-                "timestamp": time,
-            });
-
-            println!("{}", request);
-
-            s.write_message(Message::Text(request.to_string())).unwrap();
-        } else {
-            println!("NOT CONNECTED TO SOCKET")
-        }
-    }
-
-    pub fn connect_socket(&mut self) {
-        if self.socket.is_none() {
-            let (s, _response) = connect(Url::parse("wss://ws-feed.pro.coinbase.com").unwrap())
-                .expect("Can't connect to Websocket");
-            self.socket = Some(s);
-        } else {
-            println!("ALREADY CONNECTED TO SOCKET");
-        }
-    }
-
-    pub fn disconnect_socket(&mut self) {
-        if let Some(s) = self.socket.as_mut() {
-            s.close(None);
-        } else {
-            println!("ALREADY DISCONNECTED FROM SOCKET");
-        }
-    }
-
-    fn _send_subscription(&mut self, sub_type: &str, product_ids: Vec<&str>, channels: Vec<&str>) {
-        if let Some(s) = self.socket.as_mut() {
-            let request = json!({
-                "type": sub_type,
-                "product_ids": product_ids,
-                "channels": channels
-            });
-
-            s.write_message(Message::Text(request.to_string())).unwrap();
-        } else {
-            println!("NOT CONNECTED TO SOCKET")
-        }
-    }
-
-    pub fn subscribe(&mut self, product_ids: Vec<&str>, channels: Vec<&str>) {
-        self._send_subscription("subscribe", product_ids, channels);
-    }
-
-    pub fn unsubscribe(&mut self, product_ids: Vec<&str>, channels: Vec<&str>) {
-        self._send_subscription("unsubscribe", product_ids, channels);
-    }
-
-    pub fn read_from_ws(&mut self) -> TungsteniteResult<Message> {
-        if let Some(s) = self.socket.as_mut() {
-            let msg = s.read_message().expect("Error reading message");
-            Ok(msg)
-        } else {
-            Err(tungstenite::Error::Io(std::io::Error::new(
-                ErrorKind::NotConnected,
-                "NOT CONNECTED TO SOCKET",
-            )))
-        }
-    }
-
-    /// Read text from websocket
-    pub fn print_from_ws(&mut self) -> Option<String> {
-        self.read_from_ws().unwrap().into_text().ok()
-    }
-
-    /// Generate HMAC signature for authenticating our API calls
-    fn encode_signature(&self, time: &u64, method: &str, path: &str, body: &str) -> String {
-        let decoded_secret = base64::decode(&*self.secret).unwrap();
-        let mut hmac = Hmac::new(Sha256::new(), &decoded_secret);
-        let what = &*(time.to_string() + method + path + body);
-        hmac.input(what.as_bytes());
-
-        base64::encode(hmac.result().code())
+    ///
+    /// New websocket feed connection for this client's environment. The feed is separate from the
+    /// client so it can be read while REST requests are in flight.
+    ///
+    pub fn feed(&self) -> FeedConnection {
+        FeedConnection::new(self.environment.websocket_url())
     }
 
-    /// Generate correct headers based on HMAC signature for GET/POST requests
-    fn make_request_headers(&self, time: u64, signature: &String) -> reqwest::header::HeaderMap {
-        let mut headers = reqwest::header::HeaderMap::new();
+    /// Generate correct headers based on HMAC signature for GET/POST/DELETE requests
+    fn make_request_headers(
+        &self,
+        credentials: &Credentials,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> Option<HeaderMap> {
+        let time = Credentials::timestamp();
+        let signature = credentials.sign(time, method, path, body);
+        let mut headers = self.make_public_headers();
         headers.insert(
             "CB-ACCESS-SIGN",
-            reqwest::header::HeaderValue::from_str(signature).unwrap(),
+            reqwest::header::HeaderValue::from_str(&signature).ok()?,
         );
         headers.insert(
             "CB-ACCESS-TIMESTAMP",
@@ -346,12 +417,17 @@ impl AuthorizedClient {
         );
         headers.insert(
             "CB-ACCESS-KEY",
-            reqwest::header::HeaderValue::from_str(&self.access_key).unwrap(),
+            reqwest::header::HeaderValue::from_str(credentials.access_key()).ok()?,
         );
         headers.insert(
             "CB-ACCESS-PASSPHRASE",
-            reqwest::header::HeaderValue::from_str(&self.passphrase).unwrap(),
+            reqwest::header::HeaderValue::from_str(credentials.passphrase()).ok()?,
         );
+        Some(headers)
+    }
+
+    fn make_public_headers(&self) -> HeaderMap {
+        let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(
             "Content-Type",
             reqwest::header::HeaderValue::from_static("application/json"),
@@ -364,3 +440,315 @@ impl AuthorizedClient {
         headers
     }
 }
+
+///
+/// Manages user credentials and requests. Market data and feed methods come from the
+/// `PublicClient` underneath.
+///
+pub struct AuthorizedClient {
+    public: PublicClient,
+    credentials: Credentials,
+    limiter: RateLimiter,
+}
+
+impl AuthorizedClient {
+    ///
+    /// Creates a client for a REST base URL - the websocket feed is picked to match it,
+    /// see `Environment::from_rest_url`
+    ///
+    pub fn new(
+        url: &str,
+        access_key: &str,
+        passphrase: &str,
+        secret: &str,
+    ) -> Result<AuthorizedClient, CredentialsError> {
+        AuthorizedClient::with_environment(
+            Environment::from_rest_url(url),
+            access_key,
+            passphrase,
+            secret,
+        )
+    }
+
+    pub fn with_environment(
+        environment: Environment,
+        access_key: &str,
+        passphrase: &str,
+        secret: &str,
+    ) -> Result<AuthorizedClient, CredentialsError> {
+        let credentials = Credentials::new(access_key, passphrase, secret)?;
+        Ok(AuthorizedClient::with_credentials(
+            PublicClient::with_environment(environment),
+            credentials,
+        ))
+    }
+
+    ///
+    /// Adds signing to a public client, keeping its cached products
+    ///
+    pub fn with_credentials(public: PublicClient, credentials: Credentials) -> AuthorizedClient {
+        AuthorizedClient {
+            public,
+            credentials,
+            limiter: RateLimiter::new(PRIVATE_RATE_LIMIT),
+        }
+    }
+
+    pub fn public(&self) -> &PublicClient {
+        &self.public
+    }
+
+    ///
+    /// Performs currency conversion
+    ///
+    pub async fn convert(
+        &self,
+        from: &str,
+        to: &str,
+        amount: &str,
+    ) -> Result<ConversionResponse, RequestError> {
+        let conversion = Conversion::new(
+            from,
+            to,
+            Decimal::from_str(amount)
+                .map_err(|_| RequestError::InvalidRequest("invalid amount".to_string()))?,
+        );
+        self.make_request("POST", "/conversions", &conversion).await
+    }
+    ///
+    /// Gets existing orders - by id
+    ///
+    pub async fn get_order(&self, id: &str) -> Result<OpenOrder, RequestError> {
+        let method = format!("/orders/{}", id);
+        self.make_request("GET", &method, MtBody::new()).await
+    }
+
+    ///
+    /// Gets a page of existing orders, newest first - limiting query to orders with given status
+    ///
+    pub async fn get_orders(
+        &self,
+        statuses: &[&str],
+        pagination: &Pagination,
+    ) -> Result<Page<OpenOrder>, RequestError> {
+        let method_and_queries = self.form_method_and_queries("/orders", statuses);
+        self.make_paged_request(&pagination.apply(&method_and_queries))
+            .await
+    }
+
+    ///
+    /// Every order with the given statuses, fetched a page at a time
+    ///
+    pub fn stream_orders(
+        &self,
+        statuses: &[&str],
+        pagination: Pagination,
+    ) -> PageStream<'_, OpenOrder> {
+        self.paged_stream(
+            self.form_method_and_queries("/orders", statuses),
+            pagination,
+        )
+    }
+
+    fn form_method_and_queries(&self, method: &str, queries: &[&str]) -> String {
+        let mut method_and_queries = method.to_string();
+        for (i, status) in queries.iter().enumerate() {
+            method_and_queries.push_str(if i == 0 { "?status=" } else { "&status=" });
+            method_and_queries.push_str(status);
+        }
+        method_and_queries
+    }
+
+    ///
+    /// Places an order, after checking it against the product's trading rules - its size,
+    /// funds and prices are rounded to the product's increments
+    ///
+    pub async fn place_order(&self, order: &NewOrder) -> Result<OrderResponse, RequestError> {
+        let order = self
+            .public
+            .product(&order.product_id)
+            .await?
+            .validate_order(order)
+            .map_err(RequestError::InvalidOrder)?;
+        self.make_request("POST", "/orders", &order).await
+    }
+
+    ///
+    /// Cancels an order by the id the exchange gave it
+    ///
+    pub async fn cancel_order(&self, id: &str) -> Result<CanceledOrder, RequestError> {
+        let method = format!("/orders/{}", id);
+        self.make_request("DELETE", &method, MtBody::new()).await
+    }
+
+    ///
+    /// Cancels an order by the `client_oid` it was placed with
+    ///
+    pub async fn cancel_order_by_client_oid(
+        &self,
+        client_oid: &str,
+    ) -> Result<CanceledOrder, RequestError> {
+        let method = format!("/orders/client:{}", client_oid);
+        self.make_request("DELETE", &method, MtBody::new()).await
+    }
+
+    ///
+    /// Cancels every open order - for one product, or all of them
+    ///
+    pub async fn cancel_all(
+        &self,
+        product_id: Option<&str>,
+    ) -> Result<Vec<CanceledOrder>, RequestError> {
+        let method = match product_id {
+            Some(product_id) => format!("/orders?product_id={}", product_id),
+            None => "/orders".to_string(),
+        };
+        self.make_request("DELETE", &method, MtBody::new()).await
+    }
+
+    ///
+    /// Gets a page of our fills for an order or a product, newest first
+    ///
+    pub async fn get_fills(
+        &self,
+        filter: FillFilter<'_>,
+        pagination: &Pagination,
+    ) -> Result<Page<Fill>, RequestError> {
+        let method = pagination.apply(&format!("/fills?{}", filter.query()));
+        self.make_paged_request(&method).await
+    }
+
+    ///
+    /// Every fill for an order or a product, fetched a page at a time
+    ///
+    pub fn stream_fills(
+        &self,
+        filter: FillFilter<'_>,
+        pagination: Pagination,
+    ) -> PageStream<'_, Fill> {
+        self.paged_stream(format!("/fills?{}", filter.query()), pagination)
+    }
+
+    ///
+    /// Retrieves user account information
+    ///
+    pub async fn get_accounts(&self) -> Result<Vec<Account>, RequestError> {
+        self.make_request("GET", "/accounts", MtBody::new()).await
+    }
+
+    pub async fn get_account(&self, account_id: &str) -> Result<Account, RequestError> {
+        let method = format!("/accounts/{}", account_id);
+        self.make_request("GET", &method, MtBody::new()).await
+    }
+
+    ///
+    /// Gets a page of an account's ledger - every change to its balance, newest first
+    ///
+    pub async fn get_account_history(
+        &self,
+        account_id: &str,
+        pagination: &Pagination,
+    ) -> Result<Page<LedgerEntry>, RequestError> {
+        let method = pagination.apply(&format!("/accounts/{}/ledger", account_id));
+        self.make_paged_request(&method).await
+    }
+
+    ///
+    /// An account's whole ledger, fetched a page at a time
+    ///
+    pub fn stream_account_history(
+        &self,
+        account_id: &str,
+        pagination: Pagination,
+    ) -> PageStream<'_, LedgerEntry> {
+        self.paged_stream(format!("/accounts/{}/ledger", account_id), pagination)
+    }
+
+    ///
+    /// Gets a page of the funds held back from an account, for open orders and withdrawals
+    ///
+    pub async fn get_account_holds(
+        &self,
+        account_id: &str,
+        pagination: &Pagination,
+    ) -> Result<Page<Hold>, RequestError> {
+        let method = pagination.apply(&format!("/accounts/{}/holds", account_id));
+        self.make_paged_request(&method).await
+    }
+
+    ///
+    /// Every hold on an account, fetched a page at a time
+    ///
+    pub fn stream_account_holds(
+        &self,
+        account_id: &str,
+        pagination: Pagination,
+    ) -> PageStream<'_, Hold> {
+        self.paged_stream(format!("/accounts/{}/holds", account_id), pagination)
+    }
+
+    ///
+    /// Makes a signed HTTP request to a private endpoint
+    ///
+    async fn make_request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: impl Serialize,
+    ) -> Result<T, RequestError> {
+        let (response_text, _headers) = self
+            .public
+            .send_request(method, path, body, &self.limiter, Some(&self.credentials))
+            .await?;
+        self.public.parse_response(&response_text)
+    }
+
+    ///
+    /// Makes a signed GET request to a paginated private endpoint
+    ///
+    async fn make_paged_request<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<Page<T>, RequestError> {
+        let (response_text, headers) = self
+            .public
+            .send_request(
+                "GET",
+                path,
+                MtBody::new(),
+                &self.limiter,
+                Some(&self.credentials),
+            )
+            .await?;
+        self.public.parse_page(&response_text, &headers)
+    }
+
+    fn paged_stream<T: DeserializeOwned + Send + 'static>(
+        &self,
+        path: String,
+        pagination: Pagination,
+    ) -> PageStream<'_, T> {
+        paginate(pagination, move |pagination| {
+            let path = pagination.apply(&path);
+            async move { self.make_paged_request(&path).await }
+        })
+    }
+
+    ///
+    /// New feed connection that signs its subscriptions with this client's credentials, which is
+    /// required for the `user` channel and adds user fields to `full` channel messages
+    ///
+    pub fn authenticated_feed(&self) -> FeedConnection {
+        self.public
+            .feed()
+            .with_credentials(self.credentials.clone())
+    }
+}
+
+impl Deref for AuthorizedClient {
+    type Target = PublicClient;
+
+    fn deref(&self) -> &PublicClient {
+        &self.public
+    }
+}
@@ -14,16 +14,48 @@ pub struct Conversion {
 ///
 /// Conversion response
 ///
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ConversionResponse {
     id: String,
-    amount: String,
+    amount: Decimal,
     from_account_id: String,
     to_account_id: String,
     from: String,
     to: String,
 }
 
+impl ConversionResponse {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn from_account_id(&self) -> &str {
+        &self.from_account_id
+    }
+
+    pub fn to_account_id(&self) -> &str {
+        &self.to_account_id
+    }
+
+    ///
+    /// Currency converted from
+    ///
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+
+    ///
+    /// Currency converted to
+    ///
+    pub fn to(&self) -> &str {
+        &self.to
+    }
+}
+
 impl Conversion {
     pub fn new(from: &str, to: &str, amount: Decimal) -> Self {
         Conversion {
@@ -0,0 +1,106 @@
+use crate::errors::CredentialsError;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+///
+/// API key, passphrase and secret - signs REST requests and websocket subscriptions
+///
+#[derive(Clone)]
+pub struct Credentials {
+    access_key: String,
+    passphrase: String,
+    /// Decoded from base64 up front, so signing can't fail
+    secret: Vec<u8>,
+}
+
+impl Credentials {
+    ///
+    /// Checks the credentials can be used before anything is sent - the secret must be base64,
+    /// and the key and passphrase are sent as HTTP headers
+    ///
+    pub fn new(access_key: &str, passphrase: &str, secret: &str) -> Result<Self, CredentialsError> {
+        let is_header_value = |s: &str| s.bytes().all(|b| b.is_ascii_graphic() || b == b' ');
+        if !is_header_value(access_key) {
+            return Err(CredentialsError::InvalidAccessKey);
+        }
+        if !is_header_value(passphrase) {
+            return Err(CredentialsError::InvalidPassphrase);
+        }
+        let secret = base64::decode(secret.trim()).map_err(|_| CredentialsError::InvalidSecret)?;
+        Ok(Credentials {
+            access_key: access_key.to_string(),
+            passphrase: passphrase.to_string(),
+            secret,
+        })
+    }
+
+    pub fn access_key(&self) -> &str {
+        &self.access_key
+    }
+
+    pub fn passphrase(&self) -> &str {
+        &self.passphrase
+    }
+
+    /// Current time in seconds since the epoch, as the exchange expects in signed messages
+    pub(crate) fn timestamp() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// Generate HMAC signature for authenticating our API calls
+    pub(crate) fn sign(&self, time: u64, method: &str, path: &str, body: &str) -> String {
+        let mut hmac = Hmac::new(Sha256::new(), &self.secret);
+        let what = &*(time.to_string() + method + path + body);
+        hmac.input(what.as_bytes());
+
+        base64::encode(hmac.result().code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "c2VjcmV0LWtleS1ieXRlcw==";
+
+    #[test]
+    fn accepts_header_safe_keys_and_a_base64_secret() {
+        let credentials = match Credentials::new("key-1", "pass phrase", &format!(" {}\n", SECRET))
+        {
+            Ok(credentials) => credentials,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(credentials.access_key(), "key-1");
+        assert_eq!(credentials.passphrase(), "pass phrase");
+        assert_eq!(credentials.secret, b"secret-key-bytes");
+    }
+
+    #[test]
+    fn rejects_a_secret_that_is_not_base64() {
+        for secret in ["not base64!", "c2VjcmV0=x", "é"] {
+            assert!(matches!(
+                Credentials::new("key", "passphrase", secret),
+                Err(CredentialsError::InvalidSecret)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_a_key_or_passphrase_that_cannot_be_a_header() {
+        for unsafe_value in ["line\nbreak", "tab\there", "clé", "nul\0"] {
+            assert!(matches!(
+                Credentials::new(unsafe_value, "passphrase", SECRET),
+                Err(CredentialsError::InvalidAccessKey)
+            ));
+            assert!(matches!(
+                Credentials::new("key", unsafe_value, SECRET),
+                Err(CredentialsError::InvalidPassphrase)
+            ));
+        }
+    }
+}
@@ -0,0 +1,101 @@
+pub const PRODUCTION_REST_URL: &str = "https://api.pro.coinbase.com";
+pub const PRODUCTION_WEBSOCKET_URL: &str = "wss://ws-feed.pro.coinbase.com";
+pub const SANDBOX_REST_URL: &str = "https://api-public.sandbox.pro.coinbase.com";
+pub const SANDBOX_WEBSOCKET_URL: &str = "wss://ws-feed-public.sandbox.pro.coinbase.com";
+
+///
+/// Exchange endpoints - drives both the REST and websocket URLs so they always match
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Environment {
+    Production,
+    Sandbox,
+    /// Any other server, e.g. a local mock for tests
+    Custom {
+        rest_url: String,
+        websocket_url: String,
+    },
+}
+
+impl Environment {
+    pub fn custom(rest_url: &str, websocket_url: &str) -> Self {
+        Environment::Custom {
+            rest_url: rest_url.to_string(),
+            websocket_url: websocket_url.to_string(),
+        }
+    }
+
+    ///
+    /// Environment for a REST base URL. Unknown URLs become custom environments whose feed is
+    /// served by the same host, e.g. `http://localhost:8080` pairs with `ws://localhost:8080`.
+    ///
+    pub fn from_rest_url(rest_url: &str) -> Self {
+        let rest_url = rest_url.trim_end_matches('/');
+        if rest_url == PRODUCTION_REST_URL {
+            Environment::Production
+        } else if rest_url == SANDBOX_REST_URL {
+            Environment::Sandbox
+        } else {
+            let websocket_url = if let Some(rest) = rest_url.strip_prefix("https://") {
+                format!("wss://{}", rest)
+            } else if let Some(rest) = rest_url.strip_prefix("http://") {
+                format!("ws://{}", rest)
+            } else {
+                rest_url.to_string()
+            };
+            Environment::custom(rest_url, &websocket_url)
+        }
+    }
+
+    pub fn rest_url(&self) -> &str {
+        match self {
+            Environment::Production => PRODUCTION_REST_URL,
+            Environment::Sandbox => SANDBOX_REST_URL,
+            Environment::Custom { rest_url, .. } => rest_url,
+        }
+    }
+
+    pub fn websocket_url(&self) -> &str {
+        match self {
+            Environment::Production => PRODUCTION_WEBSOCKET_URL,
+            Environment::Sandbox => SANDBOX_WEBSOCKET_URL,
+            Environment::Custom { websocket_url, .. } => websocket_url,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_rest_urls_map_to_their_environment() {
+        assert_eq!(
+            Environment::from_rest_url(PRODUCTION_REST_URL),
+            Environment::Production
+        );
+        assert_eq!(
+            Environment::from_rest_url("https://api-public.sandbox.pro.coinbase.com/"),
+            Environment::Sandbox
+        );
+        assert_eq!(
+            Environment::Sandbox.websocket_url(),
+            "wss://ws-feed-public.sandbox.pro.coinbase.com"
+        );
+    }
+
+    #[test]
+    fn custom_rest_urls_pair_with_a_feed_on_the_same_host() {
+        let local = Environment::from_rest_url("http://localhost:8080/");
+        assert_eq!(local.rest_url(), "http://localhost:8080");
+        assert_eq!(local.websocket_url(), "ws://localhost:8080");
+        let mock = Environment::from_rest_url("https://mock.example.com");
+        assert_eq!(
+            mock,
+            Environment::custom("https://mock.example.com", "wss://mock.example.com")
+        );
+        // without a scheme there is nothing to translate
+        let bare = Environment::from_rest_url("localhost:8080");
+        assert_eq!(bare.websocket_url(), "localhost:8080");
+    }
+}
@@ -1,19 +1,370 @@
+use rust_decimal::Decimal;
 use std::error::Error;
 use std::fmt;
+use std::io;
 
 // Errors
 #[derive(Debug)]
 pub enum RequestError {
+    /// Bad arguments, caught before anything was sent
     InvalidRequest(String),
+    /// The request couldn't be built, or the response couldn't be read
     InternalError(String),
-    InvalidOrder(String),
-    NetworkError,
+    /// Rejected before being sent, based on the product's trading rules
+    InvalidOrder(InvalidOrderReason),
+    /// The exchange answered with an error status
+    ExchangeError {
+        status: u16,
+        /// The exchange's explanation, if the response had one
+        message: Option<String>,
+        /// Method and path, e.g. `POST /orders`
+        endpoint: String,
+    },
+    /// No answer from the exchange
+    NetworkError {
+        endpoint: String,
+        message: String,
+        timeout: bool,
+        /// Whether sending the request again can't repeat its effect - false for a `POST`
+        /// without a `client_oid`, which may have reached the exchange before the failure
+        idempotent: bool,
+    },
+}
+
+///
+/// Broad cause of a failed request, for deciding how to react to it
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Bad or missing credentials, or a key without the needed permission
+    Authentication,
+    RateLimited,
+    InsufficientFunds,
+    InvalidOrder,
+    NotFound,
+    /// Any other request the exchange refused
+    BadRequest,
+    /// The exchange failed to handle a valid request
+    Server,
+    Network,
+    Timeout,
+    Internal,
+}
+
+impl RequestError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            RequestError::InvalidRequest(_) => ErrorKind::BadRequest,
+            RequestError::InternalError(_) => ErrorKind::Internal,
+            RequestError::InvalidOrder(_) => ErrorKind::InvalidOrder,
+            RequestError::NetworkError { timeout: true, .. } => ErrorKind::Timeout,
+            RequestError::NetworkError { .. } => ErrorKind::Network,
+            RequestError::ExchangeError {
+                status,
+                message,
+                endpoint,
+            } => {
+                let message = message.as_deref().unwrap_or("").to_lowercase();
+                match status {
+                    401 | 403 => ErrorKind::Authentication,
+                    429 => ErrorKind::RateLimited,
+                    404 => ErrorKind::NotFound,
+                    500..=599 => ErrorKind::Server,
+                    _ if message.contains("insufficient funds") => ErrorKind::InsufficientFunds,
+                    _ if endpoint.starts_with("POST /orders") => ErrorKind::InvalidOrder,
+                    _ => ErrorKind::BadRequest,
+                }
+            }
+        }
+    }
+
+    ///
+    /// Whether sending the same request again later might succeed, without risking doing it twice
+    ///
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RequestError::NetworkError { idempotent, .. } => *idempotent,
+            _ => matches!(self.kind(), ErrorKind::RateLimited | ErrorKind::Server),
+        }
+    }
+
+    ///
+    /// HTTP status, if the exchange answered
+    ///
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            RequestError::ExchangeError { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The exchange's error message, if it sent one
+    ///
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            RequestError::ExchangeError { message, .. } => message.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn endpoint(&self) -> Option<&str> {
+        match self {
+            RequestError::ExchangeError { endpoint, .. }
+            | RequestError::NetworkError { endpoint, .. } => Some(endpoint),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for RequestError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid request!")
+        match self {
+            RequestError::InvalidRequest(e) => write!(f, "invalid request: {}", e),
+            RequestError::InternalError(e) => write!(f, "internal error: {}", e),
+            RequestError::InvalidOrder(reason) => write!(f, "invalid order: {}", reason),
+            RequestError::ExchangeError {
+                status,
+                message: Some(message),
+                endpoint,
+            } => write!(f, "{} failed with status {}: {}", endpoint, status, message),
+            RequestError::ExchangeError {
+                status, endpoint, ..
+            } => write!(f, "{} failed with status {}", endpoint, status),
+            RequestError::NetworkError {
+                endpoint,
+                timeout: true,
+                ..
+            } => write!(f, "{} timed out", endpoint),
+            RequestError::NetworkError {
+                endpoint, message, ..
+            } => write!(f, "{} failed: {}", endpoint, message),
+        }
     }
 }
 
 impl Error for RequestError {}
+
+///
+/// Why an order was rejected before being sent, based on the product's trading rules
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidOrderReason {
+    UnknownProduct(String),
+    MissingPrice,
+    /// Neither a size nor, for market orders, funds
+    MissingSize,
+    /// Parameters that can't be combined, e.g. `post_only` on a market order
+    InvalidOption(String),
+    /// Product isn't `online`, or trading is disabled
+    NotTrading(String),
+    CancelOnly,
+    /// Only limit orders are accepted
+    LimitOnly,
+    /// Only post only limit orders are accepted
+    PostOnly,
+    SizeTooSmall {
+        min: Decimal,
+    },
+    SizeTooLarge {
+        max: Decimal,
+    },
+    FundsTooSmall {
+        min: Decimal,
+    },
+    FundsTooLarge {
+        max: Decimal,
+    },
+}
+
+impl fmt::Display for InvalidOrderReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidOrderReason::UnknownProduct(id) => write!(f, "unknown product {}", id),
+            InvalidOrderReason::MissingPrice => write!(f, "limit order without a price"),
+            InvalidOrderReason::MissingSize => write!(f, "order without a size or funds"),
+            InvalidOrderReason::InvalidOption(option) => write!(f, "invalid option: {}", option),
+            InvalidOrderReason::NotTrading(status) => {
+                write!(f, "product is not trading: {}", status)
+            }
+            InvalidOrderReason::CancelOnly => write!(f, "product only accepts cancels"),
+            InvalidOrderReason::LimitOnly => write!(f, "product only accepts limit orders"),
+            InvalidOrderReason::PostOnly => write!(f, "product only accepts post only orders"),
+            InvalidOrderReason::SizeTooSmall { min } => {
+                write!(f, "size is below the minimum of {}", min)
+            }
+            InvalidOrderReason::SizeTooLarge { max } => {
+                write!(f, "size is above the maximum of {}", max)
+            }
+            InvalidOrderReason::FundsTooSmall { min } => {
+                write!(f, "funds are below the minimum of {}", min)
+            }
+            InvalidOrderReason::FundsTooLarge { max } => {
+                write!(f, "funds are above the maximum of {}", max)
+            }
+        }
+    }
+}
+
+///
+/// API credentials that can't be used to sign requests
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialsError {
+    /// The secret isn't valid base64
+    InvalidSecret,
+    /// The key can't be sent as a header
+    InvalidAccessKey,
+    /// The passphrase can't be sent as a header
+    InvalidPassphrase,
+}
+
+impl fmt::Display for CredentialsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialsError::InvalidSecret => write!(f, "API secret is not valid base64"),
+            CredentialsError::InvalidAccessKey => write!(f, "API key contains invalid characters"),
+            CredentialsError::InvalidPassphrase => {
+                write!(f, "API passphrase contains invalid characters")
+            }
+        }
+    }
+}
+
+impl Error for CredentialsError {}
+
+#[derive(Debug)]
+pub enum FeedError {
+    NotConnected,
+    Socket(String),
+    InvalidMessage(String),
+    /// The feed answered with an `error` message, e.g. a failed authentication
+    Rejected(String),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::NotConnected => write!(f, "not connected to socket"),
+            FeedError::Socket(e) => write!(f, "socket error: {}", e),
+            FeedError::InvalidMessage(e) => write!(f, "invalid feed message: {}", e),
+            FeedError::Rejected(e) => write!(f, "rejected by feed: {}", e),
+        }
+    }
+}
+
+impl Error for FeedError {}
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(io::Error),
+    Feed(FeedError),
+    InvalidMessage(String),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Io(e) => write!(f, "recording file error: {}", e),
+            RecordError::Feed(e) => write!(f, "{}", e),
+            RecordError::InvalidMessage(e) => write!(f, "invalid recorded message: {}", e),
+        }
+    }
+}
+
+impl Error for RecordError {}
+
+impl From<io::Error> for RecordError {
+    fn from(e: io::Error) -> Self {
+        RecordError::Io(e)
+    }
+}
+
+impl From<FeedError> for RecordError {
+    fn from(e: FeedError) -> Self {
+        RecordError::Feed(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange_error(status: u16, message: Option<&str>, endpoint: &str) -> RequestError {
+        RequestError::ExchangeError {
+            status,
+            message: message.map(str::to_string),
+            endpoint: endpoint.to_string(),
+        }
+    }
+
+    #[test]
+    fn classifies_exchange_errors_by_status() {
+        let kind = |status| exchange_error(status, None, "GET /accounts").kind();
+        assert_eq!(kind(401), ErrorKind::Authentication);
+        assert_eq!(kind(403), ErrorKind::Authentication);
+        assert_eq!(kind(404), ErrorKind::NotFound);
+        assert_eq!(kind(429), ErrorKind::RateLimited);
+        assert_eq!(kind(500), ErrorKind::Server);
+        assert_eq!(kind(503), ErrorKind::Server);
+        assert_eq!(kind(400), ErrorKind::BadRequest);
+    }
+
+    #[test]
+    fn classifies_rejected_orders_by_message_and_endpoint() {
+        let funds = exchange_error(400, Some("Insufficient funds"), "POST /orders");
+        assert_eq!(funds.kind(), ErrorKind::InsufficientFunds);
+        let order = exchange_error(400, Some("size is too accurate"), "POST /orders");
+        assert_eq!(order.kind(), ErrorKind::InvalidOrder);
+        let cancel = exchange_error(400, Some("order not found"), "DELETE /orders/1");
+        assert_eq!(cancel.kind(), ErrorKind::BadRequest);
+        // the status wins over the message
+        let auth = exchange_error(401, Some("insufficient funds"), "POST /orders");
+        assert_eq!(auth.kind(), ErrorKind::Authentication);
+    }
+
+    #[test]
+    fn classifies_local_and_network_errors() {
+        let network = |timeout| RequestError::NetworkError {
+            endpoint: "GET /time".to_string(),
+            message: "connection reset".to_string(),
+            timeout,
+            idempotent: true,
+        };
+        assert_eq!(network(true).kind(), ErrorKind::Timeout);
+        assert_eq!(network(false).kind(), ErrorKind::Network);
+        assert_eq!(
+            RequestError::InvalidOrder(InvalidOrderReason::MissingPrice).kind(),
+            ErrorKind::InvalidOrder
+        );
+        assert_eq!(
+            RequestError::InternalError("bad json".to_string()).kind(),
+            ErrorKind::Internal
+        );
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        assert!(exchange_error(429, None, "GET /fills").is_retryable());
+        assert!(exchange_error(502, None, "GET /fills").is_retryable());
+        assert!(!exchange_error(400, None, "POST /orders").is_retryable());
+        assert!(!exchange_error(401, None, "GET /fills").is_retryable());
+    }
+
+    #[test]
+    fn network_errors_are_retryable_only_when_idempotent() {
+        let network = |endpoint: &str, timeout, idempotent| RequestError::NetworkError {
+            endpoint: endpoint.to_string(),
+            message: "connection reset".to_string(),
+            timeout,
+            idempotent,
+        };
+        assert!(network("GET /fills", false, true).is_retryable());
+        assert!(network("GET /fills", true, true).is_retryable());
+        // the order may have been placed before the connection dropped
+        assert!(!network("POST /orders", false, false).is_retryable());
+        assert!(!network("POST /orders", true, false).is_retryable());
+        // with a client_oid the exchange rejects the duplicate
+        assert!(network("POST /orders", true, true).is_retryable());
+    }
+}
@@ -0,0 +1,435 @@
+use crate::orders::{DoneReason, OrderType, Side};
+use chrono::{DateTime, TimeZone, Utc};
+use rust_decimal::prelude::{Decimal, FromStr, ToPrimitive};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+///
+/// Websocket feed message - tagged by its `type` field
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedMessage {
+    Subscriptions(Subscriptions),
+    Heartbeat(Heartbeat),
+    Ticker(Ticker),
+    Snapshot(Snapshot),
+    #[serde(rename = "l2update")]
+    L2Update(L2Update),
+    Received(Received),
+    Open(Open),
+    Done(Done),
+    /// Boxed as it is by far the largest message
+    #[serde(alias = "last_match")]
+    Match(Box<Match>),
+    Change(Change),
+    Activate(Activate),
+    Status(Status),
+    Error(ErrorMessage),
+}
+
+impl FeedMessage {
+    pub fn product_id(&self) -> Option<&str> {
+        match self {
+            FeedMessage::Heartbeat(m) => Some(&m.product_id),
+            FeedMessage::Ticker(m) => Some(&m.product_id),
+            FeedMessage::Snapshot(m) => Some(&m.product_id),
+            FeedMessage::L2Update(m) => Some(&m.product_id),
+            FeedMessage::Received(m) => Some(&m.product_id),
+            FeedMessage::Open(m) => Some(&m.product_id),
+            FeedMessage::Done(m) => Some(&m.product_id),
+            FeedMessage::Match(m) => Some(&m.product_id),
+            FeedMessage::Change(m) => Some(&m.product_id),
+            FeedMessage::Activate(m) => Some(&m.product_id),
+            FeedMessage::Subscriptions(_) | FeedMessage::Status(_) | FeedMessage::Error(_) => None,
+        }
+    }
+
+    pub fn sequence(&self) -> Option<u64> {
+        match self {
+            FeedMessage::Heartbeat(m) => Some(m.sequence),
+            FeedMessage::Ticker(m) => Some(m.sequence),
+            FeedMessage::Received(m) => Some(m.sequence),
+            FeedMessage::Open(m) => Some(m.sequence),
+            FeedMessage::Done(m) => Some(m.sequence),
+            FeedMessage::Match(m) => Some(m.sequence),
+            FeedMessage::Change(m) => Some(m.sequence),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Channel subscriptions - sent in reply to every subscribe/unsubscribe
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Subscriptions {
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    pub product_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    pub sequence: u64,
+    pub last_trade_id: u64,
+    pub product_id: String,
+    pub time: DateTime<Utc>,
+}
+
+///
+/// Ticker - the first message after subscribing has no trade fields
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ticker {
+    pub sequence: u64,
+    pub product_id: String,
+    pub price: Decimal,
+    pub open_24h: Decimal,
+    pub volume_24h: Decimal,
+    pub low_24h: Decimal,
+    pub high_24h: Decimal,
+    pub volume_30d: Decimal,
+    pub best_bid: Decimal,
+    pub best_ask: Decimal,
+    pub side: Option<Side>,
+    pub time: Option<DateTime<Utc>>,
+    pub trade_id: Option<u64>,
+    pub last_size: Option<Decimal>,
+}
+
+///
+/// Level 2 snapshot - each level is [price, size]
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub product_id: String,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+///
+/// Level 2 update - each change is [side, price, new size]
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct L2Update {
+    pub product_id: String,
+    pub time: DateTime<Utc>,
+    pub changes: Vec<(Side, Decimal, Decimal)>,
+}
+
+///
+/// Order accepted by the matching engine - market orders may carry funds instead of size
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Received {
+    pub time: DateTime<Utc>,
+    pub product_id: String,
+    pub sequence: u64,
+    pub order_id: String,
+    pub order_type: OrderType,
+    pub side: Side,
+    pub size: Option<Decimal>,
+    pub price: Option<Decimal>,
+    pub funds: Option<Decimal>,
+    pub client_oid: Option<String>,
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
+}
+
+///
+/// Order now resting on the book
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Open {
+    pub time: DateTime<Utc>,
+    pub product_id: String,
+    pub sequence: u64,
+    pub order_id: String,
+    pub price: Decimal,
+    pub remaining_size: Decimal,
+    pub side: Side,
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
+}
+
+///
+/// Order no longer on the book
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Done {
+    pub time: DateTime<Utc>,
+    pub product_id: String,
+    pub sequence: u64,
+    pub order_id: String,
+    pub reason: DoneReason,
+    pub side: Side,
+    pub price: Option<Decimal>,
+    pub remaining_size: Option<Decimal>,
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
+}
+
+///
+/// Trade between two orders - `side` is the maker's side. On authenticated feeds the user and
+/// fee fields are filled in for whichever side of the trade belongs to us.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Match {
+    pub trade_id: u64,
+    pub sequence: u64,
+    pub maker_order_id: String,
+    pub taker_order_id: String,
+    pub time: DateTime<Utc>,
+    pub product_id: String,
+    pub size: Decimal,
+    pub price: Decimal,
+    pub side: Side,
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
+    pub maker_user_id: Option<String>,
+    pub maker_profile_id: Option<String>,
+    pub maker_fee_rate: Option<Decimal>,
+    pub taker_user_id: Option<String>,
+    pub taker_profile_id: Option<String>,
+    pub taker_fee_rate: Option<Decimal>,
+}
+
+///
+/// Resting order's size (or market order's funds) changed
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    pub time: DateTime<Utc>,
+    pub sequence: u64,
+    pub order_id: String,
+    pub product_id: String,
+    pub side: Side,
+    pub price: Option<Decimal>,
+    pub new_size: Option<Decimal>,
+    pub old_size: Option<Decimal>,
+    pub new_funds: Option<Decimal>,
+    pub old_funds: Option<Decimal>,
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
+}
+
+///
+/// Stop order triggered
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Activate {
+    pub product_id: String,
+    #[serde(with = "unix_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    pub order_id: String,
+    pub stop_type: String,
+    pub side: Side,
+    pub stop_price: Decimal,
+    pub size: Option<Decimal>,
+    pub funds: Option<Decimal>,
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
+}
+
+///
+/// Trading status of every product and currency
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Status {
+    pub products: Vec<ProductStatus>,
+    pub currencies: Vec<CurrencyStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProductStatus {
+    pub id: String,
+    pub base_currency: String,
+    pub quote_currency: String,
+    pub base_increment: Decimal,
+    pub quote_increment: Decimal,
+    pub status: String,
+    pub status_message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CurrencyStatus {
+    pub id: String,
+    pub name: String,
+    pub status: String,
+    pub status_message: Option<String>,
+}
+
+///
+/// Error reported by the feed, e.g. for an invalid subscription
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ErrorMessage {
+    pub message: String,
+    pub reason: Option<String>,
+}
+
+///
+/// `activate` messages carry their time as fractional unix seconds in a string
+///
+mod unix_timestamp {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        time: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let seconds = Decimal::new(time.timestamp_nanos_opt().unwrap_or(0) / 1000, 6);
+        serializer.serialize_str(&seconds.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let seconds = Decimal::from_str(&text).map_err(serde::de::Error::custom)?;
+        let nanos = (seconds * Decimal::new(1_000_000_000, 0))
+            .trunc()
+            .to_i64()
+            .ok_or_else(|| serde::de::Error::custom("timestamp out of range"))?;
+        Ok(Utc.timestamp_nanos(nanos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn parse(message: serde_json::Value) -> FeedMessage {
+        serde_json::from_value(message).unwrap()
+    }
+
+    #[test]
+    fn reads_the_first_ticker_without_trade_fields() {
+        let message = parse(json!({
+            "type": "ticker",
+            "sequence": 5928281084u64,
+            "product_id": "BTC-USD",
+            "price": "57321.01",
+            "open_24h": "56000.00",
+            "volume_24h": "18402.51",
+            "low_24h": "55500.00",
+            "high_24h": "58000.00",
+            "volume_30d": "650000.12",
+            "best_bid": "57321.00",
+            "best_ask": "57321.01"
+        }));
+        let ticker = match message {
+            FeedMessage::Ticker(ticker) => ticker,
+            other => panic!("expected a ticker, got {:?}", other),
+        };
+        assert_eq!(ticker.price, dec("57321.01"));
+        assert_eq!(ticker.side, None);
+        assert_eq!(ticker.time, None);
+        assert_eq!(ticker.trade_id, None);
+        assert_eq!(ticker.last_size, None);
+    }
+
+    #[test]
+    fn reads_last_match_as_a_match() {
+        let message = parse(json!({
+            "type": "last_match",
+            "trade_id": 10,
+            "sequence": 50,
+            "maker_order_id": "ac928c66-ca53-498f-9c13-a110027a60e8",
+            "taker_order_id": "132fb6ae-456b-4654-b4e0-d681ac05cea1",
+            "time": "2014-11-07T08:19:27.028459Z",
+            "product_id": "BTC-USD",
+            "size": "5.23512",
+            "price": "400.23",
+            "side": "sell"
+        }));
+        let trade = match message {
+            FeedMessage::Match(trade) => trade,
+            other => panic!("expected a match, got {:?}", other),
+        };
+        assert_eq!(trade.trade_id, 10);
+        assert_eq!(trade.side, Side::Sell);
+        assert_eq!(trade.taker_fee_rate, None);
+    }
+
+    #[test]
+    fn reads_activate_timestamps_in_unix_seconds() {
+        let message = parse(json!({
+            "type": "activate",
+            "product_id": "BTC-USD",
+            "timestamp": "1483736448.299000",
+            "user_id": "12",
+            "profile_id": "30000727-d308-cf50-7b1c-c06deb1934fc",
+            "order_id": "7b52009b-64fd-0a2a-49e6-d8a939753077",
+            "stop_type": "entry",
+            "side": "buy",
+            "stop_price": "80",
+            "size": "2",
+            "funds": "50"
+        }));
+        let activate = match message {
+            FeedMessage::Activate(activate) => activate,
+            other => panic!("expected an activate, got {:?}", other),
+        };
+        assert_eq!(
+            activate.timestamp,
+            Utc.timestamp_opt(1483736448, 299_000_000).unwrap()
+        );
+        assert_eq!(activate.stop_price, dec("80"));
+        // and written back the same way
+        let written = serde_json::to_value(FeedMessage::Activate(activate)).unwrap();
+        assert_eq!(written["timestamp"], "1483736448.299000");
+    }
+
+    #[test]
+    fn reads_l2update_changes_as_side_price_size() {
+        let message = parse(json!({
+            "type": "l2update",
+            "product_id": "BTC-USD",
+            "time": "2019-08-14T20:42:27.265Z",
+            "changes": [["buy", "10101.80000000", "0.162567"], ["sell", "10102.55", "0"]]
+        }));
+        let update = match message {
+            FeedMessage::L2Update(update) => update,
+            other => panic!("expected an l2update, got {:?}", other),
+        };
+        assert_eq!(
+            update.changes,
+            vec![
+                (Side::Buy, dec("10101.8"), dec("0.162567")),
+                (Side::Sell, dec("10102.55"), dec("0")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_a_market_received_with_funds_only() {
+        let message = parse(json!({
+            "type": "received",
+            "time": "2014-11-09T08:19:27.028459Z",
+            "product_id": "BTC-USD",
+            "sequence": 12,
+            "order_id": "dddec984-77a8-460a-b958-66f114b0de9b",
+            "funds": "3000.234",
+            "side": "buy",
+            "order_type": "market"
+        }));
+        let received = match message {
+            FeedMessage::Received(received) => received,
+            other => panic!("expected a received, got {:?}", other),
+        };
+        assert_eq!(received.order_type, OrderType::Market);
+        assert_eq!(received.funds, Some(dec("3000.234")));
+        assert_eq!(received.size, None);
+        assert_eq!(received.price, None);
+    }
+}
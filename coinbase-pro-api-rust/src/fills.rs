@@ -0,0 +1,203 @@
+use crate::orders::{Liquidity, Side};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+///
+/// Which fills to get - the exchange needs one or the other
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillFilter<'a> {
+    Order(&'a str),
+    Product(&'a str),
+}
+
+impl FillFilter<'_> {
+    pub(crate) fn query(&self) -> String {
+        match self {
+            FillFilter::Order(order_id) => format!("order_id={}", order_id),
+            FillFilter::Product(product_id) => format!("product_id={}", product_id),
+        }
+    }
+}
+
+///
+/// Part of one of our orders that was filled
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub trade_id: u64,
+    pub product_id: String,
+    pub order_id: String,
+    pub side: Side,
+    pub price: Decimal,
+    pub size: Decimal,
+    /// In the quote currency
+    pub fee: Decimal,
+    pub liquidity: Liquidity,
+    pub created_at: DateTime<Utc>,
+    pub settled: bool,
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
+    pub usd_volume: Option<Decimal>,
+}
+
+impl Fill {
+    ///
+    /// Quote currency traded, before fees
+    ///
+    pub fn value(&self) -> Decimal {
+        self.price * self.size
+    }
+}
+
+///
+/// Every fill of one order added up
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderFills {
+    pub order_id: String,
+    pub product_id: String,
+    pub side: Side,
+    pub size: Decimal,
+    /// Size weighted
+    pub average_price: Decimal,
+    /// Quote currency traded, before fees
+    pub value: Decimal,
+    pub fees: Decimal,
+    pub fill_count: usize,
+    pub first_fill: DateTime<Utc>,
+    pub last_fill: DateTime<Utc>,
+}
+
+impl OrderFills {
+    ///
+    /// Quote currency that left the account for a buy, or arrived for a sell
+    ///
+    pub fn net_value(&self) -> Decimal {
+        match self.side {
+            Side::Buy => self.value + self.fees,
+            Side::Sell => self.value - self.fees,
+        }
+    }
+}
+
+///
+/// Groups fills by order, in the order each order was first seen
+///
+pub fn aggregate_fills(fills: &[Fill]) -> Vec<OrderFills> {
+    let mut orders: Vec<OrderFills> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for fill in fills {
+        match index.get(fill.order_id.as_str()) {
+            Some(&i) => {
+                let order = &mut orders[i];
+                order.size += fill.size;
+                order.value += fill.value();
+                order.fees += fill.fee;
+                order.fill_count += 1;
+                order.first_fill = order.first_fill.min(fill.created_at);
+                order.last_fill = order.last_fill.max(fill.created_at);
+            }
+            None => {
+                index.insert(&fill.order_id, orders.len());
+                orders.push(OrderFills {
+                    order_id: fill.order_id.clone(),
+                    product_id: fill.product_id.clone(),
+                    side: fill.side,
+                    size: fill.size,
+                    average_price: Decimal::ZERO,
+                    value: fill.value(),
+                    fees: fill.fee,
+                    fill_count: 1,
+                    first_fill: fill.created_at,
+                    last_fill: fill.created_at,
+                })
+            }
+        }
+    }
+    for order in &mut orders {
+        if order.size > Decimal::ZERO {
+            order.average_price = order.value / order.size;
+        }
+    }
+    orders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rust_decimal::prelude::FromStr;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn fill(order_id: &str, side: Side, price: &str, size: &str, fee: &str, second: u32) -> Fill {
+        Fill {
+            trade_id: u64::from(second),
+            product_id: "BTC-USD".to_string(),
+            order_id: order_id.to_string(),
+            side,
+            price: dec(price),
+            size: dec(size),
+            fee: dec(fee),
+            liquidity: Liquidity::Maker,
+            created_at: Utc.with_ymd_and_hms(2021, 5, 1, 12, 0, second).unwrap(),
+            settled: true,
+            user_id: None,
+            profile_id: None,
+            usd_volume: None,
+        }
+    }
+
+    #[test]
+    fn adds_up_the_fills_of_an_order() {
+        let fills = vec![
+            fill("a", Side::Buy, "100", "1", "0.5", 10),
+            fill("a", Side::Buy, "103", "2", "1", 5),
+            fill("a", Side::Buy, "101", "1", "0.25", 20),
+        ];
+        let orders = aggregate_fills(&fills);
+        assert_eq!(orders.len(), 1);
+        let order = &orders[0];
+        assert_eq!(order.size, dec("4"));
+        assert_eq!(order.value, dec("407"));
+        assert_eq!(order.average_price, dec("101.75"));
+        assert_eq!(order.fees, dec("1.75"));
+        assert_eq!(order.fill_count, 3);
+        // fills don't have to arrive in time order
+        assert_eq!(order.first_fill, fills[1].created_at);
+        assert_eq!(order.last_fill, fills[2].created_at);
+    }
+
+    #[test]
+    fn net_value_charges_fees_against_the_order() {
+        let buy = aggregate_fills(&[fill("a", Side::Buy, "100", "2", "1", 0)]);
+        assert_eq!(buy[0].net_value(), dec("201"));
+        let sell = aggregate_fills(&[fill("b", Side::Sell, "100", "2", "1", 0)]);
+        assert_eq!(sell[0].net_value(), dec("199"));
+    }
+
+    #[test]
+    fn groups_interleaved_fills_in_first_seen_order() {
+        let fills = vec![
+            fill("b", Side::Sell, "200", "1", "0", 0),
+            fill("a", Side::Buy, "100", "1", "0", 1),
+            fill("b", Side::Sell, "210", "1", "0", 2),
+            fill("c", Side::Buy, "50", "1", "0", 3),
+            fill("a", Side::Buy, "110", "3", "0", 4),
+        ];
+        let orders = aggregate_fills(&fills);
+        let ids: Vec<&str> = orders.iter().map(|order| order.order_id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "a", "c"]);
+        assert_eq!(orders[0].side, Side::Sell);
+        assert_eq!(orders[0].average_price, dec("205"));
+        assert_eq!(orders[0].fill_count, 2);
+        assert_eq!(orders[1].size, dec("4"));
+        assert_eq!(orders[1].average_price, dec("107.5"));
+        assert_eq!(orders[2].fill_count, 1);
+    }
+}
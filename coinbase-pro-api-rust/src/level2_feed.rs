@@ -1,64 +1,320 @@
-use crate::client::AuthorizedClient;
-use serde_json::Value;
+use crate::errors::FeedError;
+use crate::feed::{FeedMessage, L2Update, Snapshot};
+use crate::orders::Side;
+use crate::websocket::{ConnectionState, FeedConnection, FeedEvent};
+use rust_decimal::prelude::Decimal;
+use std::collections::{BTreeMap, HashMap};
 
+///
+/// Single change from an l2update message - a size of zero removes the price level
+///
+#[derive(Debug, Clone, PartialEq)]
 pub struct Changes {
-    pub side: String,
-    pub price: String,
-    pub size: String,
+    pub side: Side,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+///
+/// Aggregated size resting at a single price
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceLevel {
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+///
+/// In-memory level 2 order book for a single product
+///
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    product_id: String,
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+}
+
+impl OrderBook {
+    pub fn new(product_id: &str) -> Self {
+        OrderBook {
+            product_id: product_id.to_string(),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+
+    pub fn product_id(&self) -> &str {
+        &self.product_id
+    }
+
+    ///
+    /// Builds a book from a level2 `snapshot` message
+    ///
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let mut book = OrderBook::new(&snapshot.product_id);
+        book.bids = snapshot.bids.iter().copied().collect();
+        book.asks = snapshot.asks.iter().copied().collect();
+        book
+    }
+
+    ///
+    /// Applies every change in a level2 `l2update` message
+    ///
+    pub fn apply_update(&mut self, update: &L2Update) {
+        for (side, price, size) in &update.changes {
+            self.apply_change(&Changes {
+                side: *side,
+                price: *price,
+                size: *size,
+            });
+        }
+    }
+
+    pub fn apply_change(&mut self, change: &Changes) {
+        let levels = match change.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        if change.size.is_zero() {
+            levels.remove(&change.price);
+        } else {
+            levels.insert(change.price, change.size);
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        self.bids
+            .iter()
+            .next_back()
+            .map(|(price, size)| PriceLevel {
+                price: *price,
+                size: *size,
+            })
+    }
+
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        self.asks.iter().next().map(|(price, size)| PriceLevel {
+            price: *price,
+            size: *size,
+        })
+    }
+
+    ///
+    /// Difference between the best ask and best bid
+    ///
+    pub fn spread(&self) -> Option<Decimal> {
+        Some(self.best_ask()?.price - self.best_bid()?.price)
+    }
+
+    ///
+    /// Top `levels` bids, best (highest) price first
+    ///
+    pub fn bids(&self, levels: usize) -> Vec<PriceLevel> {
+        self.bids
+            .iter()
+            .rev()
+            .take(levels)
+            .map(|(price, size)| PriceLevel {
+                price: *price,
+                size: *size,
+            })
+            .collect()
+    }
+
+    ///
+    /// Top `levels` asks, best (lowest) price first
+    ///
+    pub fn asks(&self, levels: usize) -> Vec<PriceLevel> {
+        self.asks
+            .iter()
+            .take(levels)
+            .map(|(price, size)| PriceLevel {
+                price: *price,
+                size: *size,
+            })
+            .collect()
+    }
+
+    ///
+    /// Size resting at exactly `price` on one side of the book
+    ///
+    pub fn size_at(&self, side: Side, price: Decimal) -> Decimal {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels.get(&price).copied().unwrap_or_default()
+    }
+
+    ///
+    /// Total size on one side of the book from the best price up to and including `price`
+    ///
+    pub fn cumulative_size(&self, side: Side, price: Decimal) -> Decimal {
+        match side {
+            Side::Buy => self.bids.range(price..).map(|(_, size)| *size).sum(),
+            Side::Sell => self.asks.range(..=price).map(|(_, size)| *size).sum(),
+        }
+    }
 }
 
 pub struct SocketQuery {
-    client: AuthorizedClient,
+    feed: FeedConnection,
     open: bool,
+    books: HashMap<String, OrderBook>,
 }
 
 impl SocketQuery {
-    pub fn new(client: AuthorizedClient) -> SocketQuery {
+    pub fn new(feed: FeedConnection) -> SocketQuery {
         SocketQuery {
-            client,
+            feed,
             open: false,
+            books: HashMap::new(),
         }
     }
 
-    pub fn open_level2(&mut self, product_ids: Vec<&str>) {
-        if self.client.is_connected() {
-            self.client.subscribe(product_ids, vec!["level2"]);
-            self.open = true
-        } else {
-            self.client.connect_socket();
-            self.client.subscribe(product_ids, vec!["level2"]);
-            self.open = true
-        }
+    pub async fn open_level2(&mut self, product_ids: Vec<&str>) -> Result<(), FeedError> {
+        self.feed.subscribe(&product_ids, &["level2"]).await;
+        self.open = true;
+        self.feed.connect().await
     }
 
-    pub fn close(&mut self) {
-        self.client.disconnect_socket();
+    pub async fn close(&mut self) {
+        self.feed.disconnect().await;
         self.open = false
     }
 
-    pub fn get_order_book(&mut self) -> Option<Changes> {
-        if self.open {
-            if let Some(val) = self.client.read_from_ws().unwrap().into_text().ok() {
-                let json_val: Value = serde_json::from_str(&val).unwrap();
-                if let Some(changes) = json_val["changes"][0].as_array() {
-                    if changes.len() == 3 {
-                        Some(Changes {
-                            side: changes[0].to_string(),
-                            price: changes[1].to_string(),
-                            size: changes[2].to_string(),
-                        })
-                    } else {
-                        None
+    ///
+    /// Reads one message from the feed and applies it to the local books.
+    /// Returns the product whose book changed, if any, or the feed's error - `NotConnected`
+    /// before `open_level2` or after `close`.
+    ///
+    /// Level 2 messages carry no sequence numbers, so gaps can't be detected here - call
+    /// `resync` to drop a product's book and have the exchange send a fresh snapshot.
+    /// Books are also dropped when the connection is lost, and rebuilt from the snapshots
+    /// sent when the subscription is replayed.
+    ///
+    pub async fn update_order_book(&mut self) -> Result<Option<String>, FeedError> {
+        if !self.open {
+            return Err(FeedError::NotConnected);
+        }
+        match self.feed.next_event().await? {
+            FeedEvent::Connection(ConnectionState::Disconnected { .. }) => {
+                self.books.clear();
+                Ok(None)
+            }
+            FeedEvent::Connection(_) => Ok(None),
+            FeedEvent::Message(FeedMessage::Snapshot(snapshot)) => {
+                self.books.insert(
+                    snapshot.product_id.to_string(),
+                    OrderBook::from_snapshot(&snapshot),
+                );
+                Ok(Some(snapshot.product_id))
+            }
+            FeedEvent::Message(FeedMessage::L2Update(update)) => {
+                match self.books.get_mut(&update.product_id) {
+                    Some(book) => {
+                        book.apply_update(&update);
+                        Ok(Some(update.product_id))
                     }
-                } else {
-                    None
+                    None => Ok(None),
                 }
-            } else {
-                None
             }
-        } else {
-            println!("NOT CONNECTED TO SOCKET");
-            None
+            _ => Ok(None),
         }
     }
+
+    pub async fn resync(&mut self, product_id: &str) {
+        self.books.remove(product_id);
+        self.feed.unsubscribe(&[product_id], &["level2"]).await;
+        self.feed.subscribe(&[product_id], &["level2"]).await;
+    }
+
+    pub fn get_order_book(&self, product_id: &str) -> Option<&OrderBook> {
+        self.books.get(product_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::prelude::FromStr;
+    use serde_json::json;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn level(price: &str, size: &str) -> PriceLevel {
+        PriceLevel {
+            price: dec(price),
+            size: dec(size),
+        }
+    }
+
+    fn book() -> OrderBook {
+        let snapshot = Snapshot {
+            product_id: "BTC-USD".to_string(),
+            bids: vec![
+                (dec("99"), dec("1")),
+                (dec("98"), dec("2")),
+                (dec("97"), dec("3")),
+            ],
+            asks: vec![(dec("101"), dec("4")), (dec("102"), dec("5"))],
+        };
+        OrderBook::from_snapshot(&snapshot)
+    }
+
+    #[test]
+    fn snapshot_sorts_levels_best_first() {
+        let book = book();
+        assert_eq!(book.product_id(), "BTC-USD");
+        assert_eq!(book.best_bid(), Some(level("99", "1")));
+        assert_eq!(book.best_ask(), Some(level("101", "4")));
+        assert_eq!(book.spread(), Some(dec("2")));
+        assert_eq!(book.bids(2), vec![level("99", "1"), level("98", "2")]);
+        assert_eq!(book.asks(5), vec![level("101", "4"), level("102", "5")]);
+    }
+
+    #[test]
+    fn updates_replace_and_remove_levels() {
+        let mut book = book();
+        let update: L2Update = serde_json::from_value(json!({
+            "product_id": "BTC-USD",
+            "time": "2021-05-01T12:00:00Z",
+            "changes": [["buy", "99", "0"], ["buy", "100", "0.5"], ["sell", "102", "6"]],
+        }))
+        .unwrap();
+        book.apply_update(&update);
+        assert_eq!(book.best_bid(), Some(level("100", "0.5")));
+        assert_eq!(book.size_at(Side::Buy, dec("99")), Decimal::ZERO);
+        assert_eq!(book.size_at(Side::Sell, dec("102")), dec("6"));
+        assert_eq!(book.spread(), Some(dec("1")));
+    }
+
+    #[test]
+    fn cumulative_size_counts_from_the_best_price() {
+        let book = book();
+        assert_eq!(book.cumulative_size(Side::Buy, dec("98")), dec("3"));
+        assert_eq!(book.cumulative_size(Side::Buy, dec("90")), dec("6"));
+        assert_eq!(book.cumulative_size(Side::Buy, dec("100")), Decimal::ZERO);
+        assert_eq!(book.cumulative_size(Side::Sell, dec("101.5")), dec("4"));
+        assert_eq!(book.cumulative_size(Side::Sell, dec("102")), dec("9"));
+    }
+
+    #[test]
+    fn empty_book_has_no_spread() {
+        let book = OrderBook::new("BTC-USD");
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(book.spread(), None);
+        assert!(book.asks(3).is_empty());
+    }
+
+    #[tokio::test]
+    async fn reading_an_unopened_feed_is_an_error() {
+        let mut query = SocketQuery::new(FeedConnection::new("ws://127.0.0.1:1"));
+        assert!(matches!(
+            query.update_order_book().await,
+            Err(FeedError::NotConnected)
+        ));
+    }
 }
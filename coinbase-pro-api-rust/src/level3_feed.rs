@@ -0,0 +1,455 @@
+use crate::client::PublicClient;
+use crate::errors::{FeedError, RequestError};
+use crate::feed::FeedMessage;
+use crate::level2_feed::PriceLevel;
+use crate::orders::Side;
+use crate::sequence::{SequenceCheck, SequenceGap, SequenceTracker};
+use crate::websocket::{ConnectionState, FeedConnection, FeedEvent};
+use rust_decimal::prelude::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+///
+/// Level 3 book snapshot from the REST API - each entry is [price, size, order_id]
+///
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Level3Snapshot {
+    pub sequence: u64,
+    pub bids: Vec<(Decimal, Decimal, String)>,
+    pub asks: Vec<(Decimal, Decimal, String)>,
+}
+
+///
+/// Single resting order
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level3Order {
+    pub order_id: String,
+    pub side: Side,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+///
+/// Where an order sits in the queue at its price level
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueuePosition {
+    pub orders_ahead: usize,
+    pub size_ahead: Decimal,
+}
+
+///
+/// In-memory level 3 (order-by-order) book for a single product
+///
+#[derive(Debug, Clone)]
+pub struct Level3Book {
+    product_id: String,
+    sequence: SequenceTracker,
+    orders: HashMap<String, Level3Order>,
+    bids: BTreeMap<Decimal, VecDeque<String>>,
+    asks: BTreeMap<Decimal, VecDeque<String>>,
+}
+
+impl Level3Book {
+    ///
+    /// Builds a book from a REST snapshot - orders keep the snapshot's queue order
+    ///
+    pub fn from_snapshot(product_id: &str, snapshot: &Level3Snapshot) -> Self {
+        let mut sequence = SequenceTracker::strict();
+        sequence.set(product_id, snapshot.sequence);
+        let mut book = Level3Book {
+            product_id: product_id.to_string(),
+            sequence,
+            orders: HashMap::new(),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        };
+        for (price, size, order_id) in &snapshot.bids {
+            book.insert(order_id, Side::Buy, *price, *size);
+        }
+        for (price, size, order_id) in &snapshot.asks {
+            book.insert(order_id, Side::Sell, *price, *size);
+        }
+        book
+    }
+
+    pub fn product_id(&self) -> &str {
+        &self.product_id
+    }
+
+    ///
+    /// Sequence number of the last message applied to the book
+    ///
+    pub fn sequence(&self) -> u64 {
+        self.sequence.last(&self.product_id).unwrap_or_default()
+    }
+
+    ///
+    /// Applies a `full` channel message. Messages at or below the book's sequence are
+    /// already reflected in the snapshot and are ignored; on a gap the book is left untouched
+    /// and must be rebuilt from a new snapshot.
+    ///
+    pub fn apply(&mut self, message: &FeedMessage) -> SequenceCheck {
+        let sequence = match message.sequence() {
+            Some(sequence) => sequence,
+            None => return SequenceCheck::Stale,
+        };
+        let check = self.sequence.check(&self.product_id, sequence);
+        if check != SequenceCheck::InOrder {
+            return check;
+        }
+        match message {
+            FeedMessage::Open(open) => {
+                self.insert(&open.order_id, open.side, open.price, open.remaining_size)
+            }
+            FeedMessage::Done(done) => self.remove(&done.order_id),
+            FeedMessage::Match(trade) => {
+                if let Some(order) = self.orders.get_mut(&trade.maker_order_id) {
+                    order.size -= trade.size;
+                }
+            }
+            FeedMessage::Change(change) => {
+                if let (Some(order), Some(new_size)) =
+                    (self.orders.get_mut(&change.order_id), change.new_size)
+                {
+                    order.size = new_size;
+                }
+            }
+            _ => {}
+        }
+        check
+    }
+
+    fn insert(&mut self, order_id: &str, side: Side, price: Decimal, size: Decimal) {
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        levels
+            .entry(price)
+            .or_default()
+            .push_back(order_id.to_string());
+        self.orders.insert(
+            order_id.to_string(),
+            Level3Order {
+                order_id: order_id.to_string(),
+                side,
+                price,
+                size,
+            },
+        );
+    }
+
+    fn remove(&mut self, order_id: &str) {
+        if let Some(order) = self.orders.remove(order_id) {
+            let levels = match order.side {
+                Side::Buy => &mut self.bids,
+                Side::Sell => &mut self.asks,
+            };
+            if let Some(queue) = levels.get_mut(&order.price) {
+                queue.retain(|id| id != order_id);
+                if queue.is_empty() {
+                    levels.remove(&order.price);
+                }
+            }
+        }
+    }
+
+    pub fn order(&self, order_id: &str) -> Option<&Level3Order> {
+        self.orders.get(order_id)
+    }
+
+    ///
+    /// Orders and size resting ahead of `order_id` at its price level
+    ///
+    pub fn queue_position(&self, order_id: &str) -> Option<QueuePosition> {
+        let order = self.orders.get(order_id)?;
+        let levels = match order.side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        let mut position = QueuePosition {
+            orders_ahead: 0,
+            size_ahead: Decimal::ZERO,
+        };
+        for id in levels.get(&order.price)? {
+            if id == order_id {
+                return Some(position);
+            }
+            position.orders_ahead += 1;
+            position.size_ahead += self.orders.get(id).map_or(Decimal::ZERO, |o| o.size);
+        }
+        None
+    }
+
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        let (price, _) = self.bids.iter().next_back()?;
+        Some(self.level(&self.bids, *price))
+    }
+
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        let (price, _) = self.asks.iter().next()?;
+        Some(self.level(&self.asks, *price))
+    }
+
+    fn level(&self, levels: &BTreeMap<Decimal, VecDeque<String>>, price: Decimal) -> PriceLevel {
+        let size = levels
+            .get(&price)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.orders.get(id))
+            .map(|order| order.size)
+            .sum();
+        PriceLevel { price, size }
+    }
+}
+
+///
+/// Outcome of reading one message from the `full` channel
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Level3Update {
+    /// The product's book changed
+    Changed(String),
+    /// Messages were missed and the book was rebuilt from a fresh snapshot
+    Resynced(SequenceGap),
+    /// Messages were missed and the snapshot request failed - the product has no book until
+    /// `sync_books` succeeds
+    Gap(SequenceGap),
+    /// The feed connection changed state. Messages missed while reconnecting show up as a gap
+    /// on each product's next message.
+    Connection(ConnectionState),
+}
+
+///
+/// Maintains level 3 books from the `full` channel, reconciled against REST snapshots
+///
+pub struct Level3Query {
+    client: PublicClient,
+    feed: FeedConnection,
+    open: bool,
+    product_ids: Vec<String>,
+    books: HashMap<String, Level3Book>,
+}
+
+impl Level3Query {
+    pub fn new(client: PublicClient) -> Level3Query {
+        Level3Query {
+            feed: client.feed(),
+            client,
+            open: false,
+            product_ids: Vec::new(),
+            books: HashMap::new(),
+        }
+    }
+
+    pub async fn open_full(&mut self, product_ids: Vec<&str>) -> Result<(), FeedError> {
+        self.product_ids
+            .extend(product_ids.iter().map(|s| s.to_string()));
+        self.feed.subscribe(&product_ids, &["full"]).await;
+        self.open = true;
+        self.feed.connect().await
+    }
+
+    pub async fn close(&mut self) {
+        self.feed.disconnect().await;
+        self.open = false
+    }
+
+    ///
+    /// Loads a REST snapshot for every subscribed product. Call after `open_full` so feed
+    /// messages newer than the snapshot are waiting on the socket; older ones are skipped.
+    ///
+    pub async fn sync_books(&mut self) -> Result<(), RequestError> {
+        for product_id in self.product_ids.clone() {
+            self.sync_book(&product_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn sync_book(&mut self, product_id: &str) -> Result<(), RequestError> {
+        let snapshot = self.client.get_level3_book(product_id).await?;
+        self.books.insert(
+            product_id.to_string(),
+            Level3Book::from_snapshot(product_id, &snapshot),
+        );
+        Ok(())
+    }
+
+    ///
+    /// Reads one message from the feed and applies it to the local books, rebuilding a book
+    /// from a new snapshot if its sequence numbers show a gap. Returns `None` for messages that
+    /// changed no book, or the feed's error - `NotConnected` before `open_full` or after `close`.
+    ///
+    pub async fn update_order_book(&mut self) -> Result<Option<Level3Update>, FeedError> {
+        if !self.open {
+            return Err(FeedError::NotConnected);
+        }
+        let message = match self.feed.next_event().await? {
+            FeedEvent::Message(message) => message,
+            FeedEvent::Connection(state) => return Ok(Some(Level3Update::Connection(state))),
+        };
+        let product_id = match message.product_id() {
+            Some(product_id) => product_id.to_string(),
+            None => return Ok(None),
+        };
+        let check = match self.books.get_mut(&product_id) {
+            Some(book) => book.apply(&message),
+            None => return Ok(None),
+        };
+        match check {
+            SequenceCheck::InOrder => Ok(Some(Level3Update::Changed(product_id))),
+            SequenceCheck::Stale => Ok(None),
+            SequenceCheck::Gap(gap) => {
+                self.books.remove(&product_id);
+                match self.sync_book(&product_id).await {
+                    Ok(()) => Ok(Some(Level3Update::Resynced(gap))),
+                    Err(_) => Ok(Some(Level3Update::Gap(gap))),
+                }
+            }
+        }
+    }
+
+    ///
+    /// Whether every subscribed product has a book that is consistent with the feed
+    ///
+    pub fn is_synced(&self) -> bool {
+        self.product_ids
+            .iter()
+            .all(|product_id| self.books.contains_key(product_id))
+    }
+
+    pub fn get_order_book(&self, product_id: &str) -> Option<&Level3Book> {
+        self.books.get(product_id)
+    }
+
+    ///
+    /// Queue position of one of our own resting orders
+    ///
+    pub fn queue_position(&self, product_id: &str, order_id: &str) -> Option<QueuePosition> {
+        self.books.get(product_id)?.queue_position(order_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::prelude::FromStr;
+    use serde_json::json;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn book() -> Level3Book {
+        let snapshot = Level3Snapshot {
+            sequence: 100,
+            bids: vec![
+                (dec("99"), dec("1"), "bid-1".to_string()),
+                (dec("99"), dec("2"), "bid-2".to_string()),
+            ],
+            asks: vec![(dec("101"), dec("3"), "ask-1".to_string())],
+        };
+        Level3Book::from_snapshot("BTC-USD", &snapshot)
+    }
+
+    fn open(sequence: u64, order_id: &str, price: &str, size: &str) -> FeedMessage {
+        serde_json::from_value(json!({
+            "type": "open",
+            "time": "2021-05-01T12:00:00Z",
+            "product_id": "BTC-USD",
+            "sequence": sequence,
+            "order_id": order_id,
+            "price": price,
+            "remaining_size": size,
+            "side": "buy",
+        }))
+        .unwrap()
+    }
+
+    fn done(sequence: u64, order_id: &str) -> FeedMessage {
+        serde_json::from_value(json!({
+            "type": "done",
+            "time": "2021-05-01T12:00:00Z",
+            "product_id": "BTC-USD",
+            "sequence": sequence,
+            "order_id": order_id,
+            "reason": "canceled",
+            "side": "buy",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn snapshot_keeps_queue_order() {
+        let book = book();
+        assert_eq!(book.sequence(), 100);
+        assert_eq!(
+            book.best_bid(),
+            Some(PriceLevel {
+                price: dec("99"),
+                size: dec("3"),
+            })
+        );
+        assert_eq!(
+            book.queue_position("bid-2"),
+            Some(QueuePosition {
+                orders_ahead: 1,
+                size_ahead: dec("1"),
+            })
+        );
+    }
+
+    #[test]
+    fn applies_messages_in_sequence() {
+        let mut book = book();
+        assert_eq!(
+            book.apply(&open(101, "bid-3", "99", "4")),
+            SequenceCheck::InOrder
+        );
+        assert_eq!(book.apply(&done(102, "bid-1")), SequenceCheck::InOrder);
+        assert_eq!(book.sequence(), 102);
+        assert_eq!(book.order("bid-1"), None);
+        assert_eq!(
+            book.queue_position("bid-3"),
+            Some(QueuePosition {
+                orders_ahead: 1,
+                size_ahead: dec("2"),
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_messages_covered_by_the_snapshot() {
+        let mut book = book();
+        assert_eq!(book.apply(&done(100, "bid-1")), SequenceCheck::Stale);
+        assert_eq!(book.apply(&done(90, "bid-1")), SequenceCheck::Stale);
+        assert!(book.order("bid-1").is_some());
+        assert_eq!(book.sequence(), 100);
+    }
+
+    #[test]
+    fn gap_leaves_the_book_untouched() {
+        let mut book = book();
+        assert_eq!(
+            book.apply(&done(103, "bid-1")),
+            SequenceCheck::Gap(SequenceGap {
+                product_id: "BTC-USD".to_string(),
+                expected: 101,
+                received: 103,
+            })
+        );
+        assert!(book.order("bid-1").is_some());
+        assert_eq!(book.sequence(), 100);
+    }
+
+    #[tokio::test]
+    async fn reading_an_unopened_feed_is_an_error() {
+        let mut query = Level3Query::new(PublicClient::new("http://127.0.0.1:1"));
+        assert!(matches!(
+            query.update_order_book().await,
+            Err(FeedError::NotConnected)
+        ));
+    }
+}
@@ -1,6 +1,20 @@
 pub mod accounts;
 pub mod client;
 pub mod conversion;
+pub mod credentials;
+pub mod environment;
 pub mod errors;
+pub mod feed;
+pub mod fills;
 pub mod level2_feed;
+pub mod level3_feed;
+pub mod market_data;
 pub mod orders;
+pub mod pagination;
+pub mod products;
+pub mod rate_limit;
+pub mod recorder;
+pub mod replay;
+pub mod sequence;
+pub mod user_channel;
+pub mod websocket;
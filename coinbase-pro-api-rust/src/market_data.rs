@@ -0,0 +1,320 @@
+use crate::feed::Snapshot;
+use crate::level2_feed::OrderBook;
+use crate::orders::Side;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+///
+/// Most candles the exchange returns for one request
+///
+pub const MAX_CANDLES_PER_REQUEST: i64 = 300;
+
+///
+/// Candle widths the exchange supports
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+    OneHour,
+    SixHours,
+    OneDay,
+}
+
+impl Granularity {
+    pub fn seconds(self) -> i64 {
+        match self {
+            Granularity::OneMinute => 60,
+            Granularity::FiveMinutes => 300,
+            Granularity::FifteenMinutes => 900,
+            Granularity::OneHour => 3600,
+            Granularity::SixHours => 21600,
+            Granularity::OneDay => 86400,
+        }
+    }
+}
+
+///
+/// Splits `start..end` into ranges of at most `MAX_CANDLES_PER_REQUEST` candles, oldest first.
+/// Each range starts where the last one ended.
+///
+pub(crate) fn candle_ranges(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    granularity: Granularity,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let step = Duration::seconds(granularity.seconds() * MAX_CANDLES_PER_REQUEST);
+    let mut ranges = Vec::new();
+    let mut range_start = start;
+    while range_start < end {
+        let range_end = (range_start + step).min(end);
+        ranges.push((range_start, range_end));
+        range_start = range_end;
+    }
+    ranges
+}
+
+///
+/// Candles from every range's response, oldest first - keeping only those within `start..=end`,
+/// and the boundary candle neighbouring ranges share once
+///
+pub(crate) fn merge_candles<I: IntoIterator<Item = Candle>>(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    candles: I,
+) -> Vec<Candle> {
+    candles
+        .into_iter()
+        .filter(|candle| candle.time >= start && candle.time <= end)
+        .map(|candle| (candle.time, candle))
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .collect()
+}
+
+///
+/// Candle as the exchange sends it - [time, low, high, open, close, volume]
+///
+#[derive(Serialize, Deserialize)]
+struct CandleRow(i64, Decimal, Decimal, Decimal, Decimal, Decimal);
+
+///
+/// Price and volume over one bucket - `time` is the start of the bucket
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "CandleRow", into = "CandleRow")]
+pub struct Candle {
+    pub time: DateTime<Utc>,
+    pub low: Decimal,
+    pub high: Decimal,
+    pub open: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+}
+
+impl From<CandleRow> for Candle {
+    fn from(row: CandleRow) -> Self {
+        Candle {
+            time: Utc.timestamp_opt(row.0, 0).single().unwrap_or_default(),
+            low: row.1,
+            high: row.2,
+            open: row.3,
+            close: row.4,
+            volume: row.5,
+        }
+    }
+}
+
+impl From<Candle> for CandleRow {
+    fn from(candle: Candle) -> Self {
+        CandleRow(
+            candle.time.timestamp(),
+            candle.low,
+            candle.high,
+            candle.open,
+            candle.close,
+            candle.volume,
+        )
+    }
+}
+
+///
+/// Latest trade and best prices for a product
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProductTicker {
+    pub trade_id: u64,
+    pub price: Decimal,
+    pub size: Decimal,
+    pub bid: Decimal,
+    pub ask: Decimal,
+    pub volume: Decimal,
+    pub time: DateTime<Utc>,
+}
+
+///
+/// Trade from a product's trade history - `side` is the maker's side
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProductTrade {
+    pub time: DateTime<Utc>,
+    pub trade_id: u64,
+    pub price: Decimal,
+    pub size: Decimal,
+    pub side: Side,
+}
+
+///
+/// 24 hour statistics for a product
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProductStats {
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub volume: Decimal,
+    pub last: Option<Decimal>,
+    pub volume_30day: Option<Decimal>,
+}
+
+///
+/// How much of the book to fetch - the full order-by-order book is `get_level3_book`
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookLevel {
+    /// Only the best bid and ask
+    Best,
+    /// Every price level, aggregated
+    Aggregated,
+}
+
+impl BookLevel {
+    pub fn level(self) -> u8 {
+        match self {
+            BookLevel::Best => 1,
+            BookLevel::Aggregated => 2,
+        }
+    }
+}
+
+///
+/// Aggregated book snapshot from the REST API - each entry is [price, size, number of orders]
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BookSnapshot {
+    pub sequence: u64,
+    pub bids: Vec<(Decimal, Decimal, u64)>,
+    pub asks: Vec<(Decimal, Decimal, u64)>,
+}
+
+impl BookSnapshot {
+    ///
+    /// Local book seeded from the snapshot, e.g. before the feed's first level2 snapshot
+    ///
+    pub fn to_order_book(&self, product_id: &str) -> OrderBook {
+        OrderBook::from_snapshot(&Snapshot {
+            product_id: product_id.to_string(),
+            bids: self
+                .bids
+                .iter()
+                .map(|(price, size, _)| (*price, *size))
+                .collect(),
+            asks: self
+                .asks
+                .iter()
+                .map(|(price, size, _)| (*price, *size))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Currency {
+    pub id: String,
+    pub name: String,
+    pub min_size: Decimal,
+    pub status: Option<String>,
+    pub message: Option<String>,
+    pub max_precision: Option<Decimal>,
+}
+
+///
+/// Exchange clock
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerTime {
+    pub iso: DateTime<Utc>,
+    pub epoch: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::prelude::FromStr;
+    use serde_json::json;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn at(minute: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_620_000_000, 0).unwrap() + Duration::minutes(minute)
+    }
+
+    fn candle(minute: i64, close: &str) -> Candle {
+        Candle {
+            time: at(minute),
+            low: dec(close),
+            high: dec(close),
+            open: dec(close),
+            close: dec(close),
+            volume: dec("1"),
+        }
+    }
+
+    #[test]
+    fn splits_ranges_at_the_most_candles_per_request() {
+        let ranges = candle_ranges(at(0), at(700), Granularity::OneMinute);
+        assert_eq!(
+            ranges,
+            vec![(at(0), at(300)), (at(300), at(600)), (at(600), at(700))]
+        );
+        // one request when it fits, none for an empty range
+        let hours = candle_ranges(at(0), at(300 * 60), Granularity::OneHour);
+        assert_eq!(hours, vec![(at(0), at(300 * 60))]);
+        assert!(candle_ranges(at(10), at(10), Granularity::OneMinute).is_empty());
+        assert!(candle_ranges(at(10), at(0), Granularity::OneMinute).is_empty());
+    }
+
+    #[test]
+    fn merges_ranges_oldest_first_without_duplicates() {
+        // the exchange answers newest first, and both ranges hold the boundary candle
+        let first = vec![candle(300, "3"), candle(299, "2"), candle(0, "1")];
+        let second = vec![candle(301, "5"), candle(300, "4")];
+        let candles = merge_candles(at(0), at(301), first.into_iter().chain(second));
+        let times: Vec<DateTime<Utc>> = candles.iter().map(|candle| candle.time).collect();
+        assert_eq!(times, vec![at(0), at(299), at(300), at(301)]);
+        // the later range's copy of the boundary candle wins
+        assert_eq!(candles[2].close, dec("4"));
+    }
+
+    #[test]
+    fn merging_drops_candles_outside_the_range() {
+        let candles = vec![
+            candle(-1, "1"),
+            candle(0, "2"),
+            candle(5, "3"),
+            candle(6, "4"),
+        ];
+        let merged = merge_candles(at(0), at(5), candles);
+        assert_eq!(merged, vec![candle(0, "2"), candle(5, "3")]);
+    }
+
+    #[test]
+    fn reads_candles_from_numeric_arrays() {
+        let candles: Vec<Candle> = serde_json::from_value(json!([
+            [1620000060, 57000.5, 57100, 57010.25, 57090, 12.5],
+            [1620000000, 56900, 57020.75, 56950, 57010.25, 0.001]
+        ]))
+        .unwrap();
+        assert_eq!(
+            candles[0],
+            Candle {
+                time: at(1),
+                low: dec("57000.5"),
+                high: dec("57100"),
+                open: dec("57010.25"),
+                close: dec("57090"),
+                volume: dec("12.5"),
+            }
+        );
+        assert_eq!(candles[1].time, at(0));
+        assert_eq!(candles[1].volume, dec("0.001"));
+        // and written back the same way
+        let written = serde_json::to_value(&candles[1]).unwrap();
+        assert_eq!(written[0], 1620000000);
+    }
+}
@@ -1,124 +1,530 @@
+use crate::errors::RequestError;
+use chrono::{DateTime, Utc};
 use rust_decimal::prelude::Decimal;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
 ///
-/// Order types
+/// Order side - buy or sell
 ///
-pub enum Order {
-    MarketOrder,
-    LimitOrder,
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
 }
 
-///
-/// Market order
-///
-#[derive(Serialize, Deserialize, Debug)]
-pub struct MarketOrder {
-    r#type: String,
-    size: Decimal,
-    side: String,
-    product_id: String,
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
 }
 
-impl MarketOrder {
-    pub fn new(r#type: String, size: Decimal, side: String, product_id: String) -> Self {
-        MarketOrder {
-            r#type,
-            size,
-            side,
-            product_id,
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Buy => write!(f, "buy"),
+            Side::Sell => write!(f, "sell"),
+        }
+    }
+}
+
+impl FromStr for Side {
+    type Err = RequestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buy" => Ok(Side::Buy),
+            "sell" => Ok(Side::Sell),
+            _ => Err(RequestError::InvalidRequest(format!("invalid side: {}", s))),
         }
     }
 }
 
 ///
-/// Limit order
+/// Order type as reported by the exchange
 ///
-#[derive(Serialize, Deserialize, Debug)]
-pub struct LimitOrder {
-    r#type: String,
-    price: Decimal,
-    size: Decimal,
-    side: String,
-    product_id: String,
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderType {
+    Limit,
+    Market,
+    Stop,
+}
+
+///
+/// Why an order left the book
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DoneReason {
+    Filled,
+    Canceled,
+}
+
+///
+/// Whether a fill added liquidity to the book or took it
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    #[serde(rename = "M")]
+    Maker,
+    #[serde(rename = "T")]
+    Taker,
+}
+
+///
+/// How long an order stays on the book
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeInForce {
+    #[serde(rename = "GTC")]
+    GoodTillCanceled,
+    /// Canceled after the order's `cancel_after`
+    #[serde(rename = "GTT")]
+    GoodTillTime,
+    #[serde(rename = "IOC")]
+    ImmediateOrCancel,
+    #[serde(rename = "FOK")]
+    FillOrKill,
+}
+
+///
+/// Lifetime of a good till time order
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CancelAfter {
+    Min,
+    Hour,
+    Day,
+}
+
+///
+/// What happens when an order would match another order of ours
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    /// Decrease the larger order by the smaller one and cancel the smaller one
+    #[serde(rename = "dc")]
+    DecreaseAndCancel,
+    #[serde(rename = "co")]
+    CancelOldest,
+    #[serde(rename = "cn")]
+    CancelNewest,
+    #[serde(rename = "cb")]
+    CancelBoth,
 }
 
-impl LimitOrder {
-    pub fn new(
-        r#type: String,
-        price: Decimal,
-        size: Decimal,
-        side: String,
-        product_id: String,
-    ) -> Self {
-        LimitOrder {
-            r#type,
-            price,
-            size,
+///
+/// When a stop order triggers - `Loss` at or below the stop price, `Entry` at or above it
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StopDirection {
+    Loss,
+    Entry,
+}
+
+///
+/// Order to place, serialized as the exchange expects it. Start from `limit`, `market` or
+/// `market_funds` and chain the optional parameters, e.g.
+/// `NewOrder::limit("BTC-USD", Side::Buy, price, size).post_only().client_oid(id)`
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NewOrder {
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    pub side: Side,
+    pub product_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<Decimal>,
+    /// Quote currency to spend or receive - market orders only, instead of `size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub funds: Option<Decimal>,
+    /// Our own id for the order, so a retried request can't place it twice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_oid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_in_force: Option<TimeInForce>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cancel_after: Option<CancelAfter>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub post_only: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stp: Option<SelfTradePrevention>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<StopDirection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_price: Option<Decimal>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl NewOrder {
+    pub fn limit(product_id: &str, side: Side, price: Decimal, size: Decimal) -> Self {
+        NewOrder {
+            price: Some(price),
+            size: Some(size),
+            ..NewOrder::new(OrderType::Limit, product_id, side)
+        }
+    }
+
+    pub fn market(product_id: &str, side: Side, size: Decimal) -> Self {
+        NewOrder {
+            size: Some(size),
+            ..NewOrder::new(OrderType::Market, product_id, side)
+        }
+    }
+
+    ///
+    /// Market order for an amount of the quote currency, e.g. buy $100 of BTC
+    ///
+    pub fn market_funds(product_id: &str, side: Side, funds: Decimal) -> Self {
+        NewOrder {
+            funds: Some(funds),
+            ..NewOrder::new(OrderType::Market, product_id, side)
+        }
+    }
+
+    fn new(order_type: OrderType, product_id: &str, side: Side) -> Self {
+        NewOrder {
+            order_type,
             side,
-            product_id,
+            product_id: product_id.to_string(),
+            price: None,
+            size: None,
+            funds: None,
+            client_oid: None,
+            time_in_force: None,
+            cancel_after: None,
+            post_only: false,
+            stp: None,
+            stop: None,
+            stop_price: None,
         }
     }
+
+    pub fn client_oid(mut self, client_oid: &str) -> Self {
+        self.client_oid = Some(client_oid.to_string());
+        self
+    }
+
+    pub fn time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = Some(time_in_force);
+        self
+    }
+
+    ///
+    /// Makes the order good till time, canceled after `cancel_after`
+    ///
+    pub fn cancel_after(mut self, cancel_after: CancelAfter) -> Self {
+        self.time_in_force = Some(TimeInForce::GoodTillTime);
+        self.cancel_after = Some(cancel_after);
+        self
+    }
+
+    ///
+    /// Rejects the order instead of letting it take liquidity - limit orders only
+    ///
+    pub fn post_only(mut self) -> Self {
+        self.post_only = true;
+        self
+    }
+
+    pub fn stp(mut self, stp: SelfTradePrevention) -> Self {
+        self.stp = Some(stp);
+        self
+    }
+
+    ///
+    /// Keeps the order off the book until the last trade price crosses `stop_price`
+    ///
+    pub fn stop(mut self, direction: StopDirection, stop_price: Decimal) -> Self {
+        self.stop = Some(direction);
+        self.stop_price = Some(stop_price);
+        self
+    }
+}
+
+///
+/// Order status as reported by the exchange
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Received,
+    Open,
+    /// Accepted but not yet on the book
+    Pending,
+    /// Stop order waiting for its trigger
+    Active,
+    Done,
+    Rejected,
+    /// Statuses added by the exchange since this was written
+    #[serde(other)]
+    Other,
 }
 
 ///
 /// Order response
 ///
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct OrderResponse {
     id: String,
     price: Option<Decimal>,
-    size: String,
+    size: Option<Decimal>,
     product_id: String,
-    side: String,
-    stp: String,
-    funds: String,
-    r#type: String,
-    time_in_force: Option<String>,
+    side: Side,
+    stp: Option<SelfTradePrevention>,
+    funds: Option<Decimal>,
+    specified_funds: Option<Decimal>,
+    #[serde(rename = "type")]
+    order_type: OrderType,
+    time_in_force: Option<TimeInForce>,
+    #[serde(default)]
     post_only: bool,
-    created_at: String,
-    fill_fees: String,
-    filled_size: String,
-    executed_value: String,
-    status: String,
+    stop: Option<StopDirection>,
+    stop_price: Option<Decimal>,
+    created_at: DateTime<Utc>,
+    fill_fees: Decimal,
+    filled_size: Decimal,
+    executed_value: Decimal,
+    status: OrderStatus,
     settled: bool,
 }
 
+impl OrderResponse {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    ///
+    /// Limit price - `None` for market orders
+    ///
+    pub fn price(&self) -> Option<Decimal> {
+        self.price
+    }
+
+    ///
+    /// `None` for market orders placed by funds
+    ///
+    pub fn size(&self) -> Option<Decimal> {
+        self.size
+    }
+
+    pub fn product_id(&self) -> &str {
+        &self.product_id
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn stp(&self) -> Option<SelfTradePrevention> {
+        self.stp
+    }
+
+    ///
+    /// Quote currency available to a market order, after fees are set aside
+    ///
+    pub fn funds(&self) -> Option<Decimal> {
+        self.funds
+    }
+
+    ///
+    /// Quote currency a market order was placed for
+    ///
+    pub fn specified_funds(&self) -> Option<Decimal> {
+        self.specified_funds
+    }
+
+    pub fn order_type(&self) -> OrderType {
+        self.order_type
+    }
+
+    pub fn time_in_force(&self) -> Option<TimeInForce> {
+        self.time_in_force
+    }
+
+    pub fn post_only(&self) -> bool {
+        self.post_only
+    }
+
+    pub fn stop(&self) -> Option<StopDirection> {
+        self.stop
+    }
+
+    pub fn stop_price(&self) -> Option<Decimal> {
+        self.stop_price
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    pub fn fill_fees(&self) -> Decimal {
+        self.fill_fees
+    }
+
+    pub fn filled_size(&self) -> Decimal {
+        self.filled_size
+    }
+
+    ///
+    /// Quote currency traded so far, before fees
+    ///
+    pub fn executed_value(&self) -> Decimal {
+        self.executed_value
+    }
+
+    pub fn status(&self) -> OrderStatus {
+        self.status
+    }
+
+    pub fn settled(&self) -> bool {
+        self.settled
+    }
+}
+
 ///
-/// Open orders
+/// Order as listed by the orders endpoints - the placed order's fields plus when and why it
+/// finished. Derefs to `OrderResponse` for the shared fields.
 ///
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct OpenOrder {
-    id: String,
-    price: Option<String>,
-    size: Option<String>,
-    product_id: String,
+    #[serde(flatten)]
+    order: OrderResponse,
     profile_id: String,
-    side: String,
-    funds: Option<String>,
-    specified_funds: Option<String>,
-    stp: Option<String>,
-    r#type: String,
-    time_in_force: Option<String>,
-    post_only: bool,
-    created_at: String,
-    done_at: Option<String>,
-    done_reason: Option<String>,
-    fill_fees: String,
-    filled_size: String,
-    executed_value: String,
-    status: String,
-    settled: bool,
+    done_at: Option<DateTime<Utc>>,
+    done_reason: Option<DoneReason>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ListOrder {
-    status: String,
+impl OpenOrder {
+    pub fn order(&self) -> &OrderResponse {
+        &self.order
+    }
+
+    pub fn profile_id(&self) -> &str {
+        &self.profile_id
+    }
+
+    pub fn done_at(&self) -> Option<DateTime<Utc>> {
+        self.done_at
+    }
+
+    pub fn done_reason(&self) -> Option<DoneReason> {
+        self.done_reason
+    }
 }
 
-impl ListOrder {
-    pub fn new(status: &str) -> Self {
-        ListOrder {
-            status: status.to_string(),
-        }
+impl Deref for OpenOrder {
+    type Target = OrderResponse;
+
+    fn deref(&self) -> &OrderResponse {
+        &self.order
+    }
+}
+
+///
+/// Order the exchange canceled - the cancel endpoints only answer with ids
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct CanceledOrder {
+    pub id: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::prelude::FromStr;
+    use serde_json::json;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn reads_an_open_limit_order() {
+        let order: OpenOrder = serde_json::from_value(json!({
+            "id": "d0c5340b-6d6c-49d9-b567-48c4bfca13d2",
+            "price": "0.10000000",
+            "size": "0.01000000",
+            "product_id": "BTC-USD",
+            "profile_id": "8058d771-2d88-4f0f-ab6e-299c153d4308",
+            "side": "buy",
+            "type": "limit",
+            "time_in_force": "GTC",
+            "post_only": true,
+            "stp": "dc",
+            "created_at": "2016-12-08T20:02:28.53864Z",
+            "fill_fees": "0.0000000000000000",
+            "filled_size": "0.00000000",
+            "executed_value": "0.0000000000000000",
+            "status": "open",
+            "settled": false
+        }))
+        .unwrap();
+        assert_eq!(order.id(), "d0c5340b-6d6c-49d9-b567-48c4bfca13d2");
+        assert_eq!(order.profile_id(), "8058d771-2d88-4f0f-ab6e-299c153d4308");
+        assert_eq!(order.order_type(), OrderType::Limit);
+        assert_eq!(order.side(), Side::Buy);
+        assert_eq!(order.price(), Some(dec("0.1")));
+        assert_eq!(order.size(), Some(dec("0.01")));
+        assert_eq!(order.time_in_force(), Some(TimeInForce::GoodTillCanceled));
+        assert_eq!(order.stp(), Some(SelfTradePrevention::DecreaseAndCancel));
+        assert!(order.post_only());
+        assert_eq!(order.status(), OrderStatus::Open);
+        assert_eq!(order.done_at(), None);
+        assert_eq!(order.done_reason(), None);
+    }
+
+    #[test]
+    fn reads_a_done_market_order() {
+        let order: OpenOrder = serde_json::from_value(json!({
+            "id": "b227e691-365c-470f-a860-a9b4a37dd1d8",
+            "size": "1.00000000",
+            "product_id": "BTC-USD",
+            "profile_id": "8058d771-2d88-4f0f-ab6e-299c153d4308",
+            "side": "sell",
+            "funds": "9.9750623400000000",
+            "specified_funds": "10.0000000000000000",
+            "type": "market",
+            "post_only": false,
+            "created_at": "2016-12-08T20:09:05.508883Z",
+            "done_at": "2016-12-08T20:09:05.527Z",
+            "done_reason": "filled",
+            "fill_fees": "0.0249376391550000",
+            "filled_size": "0.01291771",
+            "executed_value": "9.9750556620000000",
+            "status": "done",
+            "settled": true
+        }))
+        .unwrap();
+        assert_eq!(order.order_type(), OrderType::Market);
+        assert_eq!(order.price(), None);
+        assert_eq!(order.time_in_force(), None);
+        assert_eq!(order.funds(), Some(dec("9.97506234")));
+        assert_eq!(order.specified_funds(), Some(dec("10")));
+        assert_eq!(order.fill_fees(), dec("0.024937639155"));
+        assert_eq!(order.filled_size(), dec("0.01291771"));
+        assert_eq!(order.executed_value(), dec("9.975055662"));
+        assert_eq!(order.status(), OrderStatus::Done);
+        assert!(order.settled());
+        assert_eq!(order.done_reason(), Some(DoneReason::Filled));
+        assert_eq!(
+            order.done_at().map(|time| time.to_rfc3339()),
+            Some("2016-12-08T20:09:05.527+00:00".to_string())
+        );
     }
 }
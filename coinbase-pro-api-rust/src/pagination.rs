@@ -0,0 +1,217 @@
+use crate::errors::RequestError;
+use futures_util::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+
+///
+/// Cursor arguments for paginated endpoints. Results are newest first, so `after` a cursor
+/// gets older items and `before` it gets newer ones.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pagination {
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Items per page - the exchange's default and maximum is 100
+    pub limit: Option<u32>,
+}
+
+impl Pagination {
+    pub fn before(cursor: &str) -> Self {
+        Pagination {
+            before: Some(cursor.to_string()),
+            ..Pagination::default()
+        }
+    }
+
+    pub fn after(cursor: &str) -> Self {
+        Pagination {
+            after: Some(cursor.to_string()),
+            ..Pagination::default()
+        }
+    }
+
+    ///
+    /// Appends the cursor arguments to a request path
+    ///
+    pub(crate) fn apply(&self, path: &str) -> String {
+        let mut query = Vec::new();
+        if let Some(limit) = self.limit {
+            query.push(format!("limit={}", limit));
+        }
+        if let Some(before) = &self.before {
+            query.push(format!("before={}", before));
+        }
+        if let Some(after) = &self.after {
+            query.push(format!("after={}", after));
+        }
+        if query.is_empty() {
+            path.to_string()
+        } else if path.contains('?') {
+            format!("{}&{}", path, query.join("&"))
+        } else {
+            format!("{}?{}", path, query.join("&"))
+        }
+    }
+}
+
+///
+/// One page of results, with the cursors for the pages either side of it
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass to `Pagination::before` for newer items
+    pub before: Option<String>,
+    /// Pass to `Pagination::after` for older items
+    pub after: Option<String>,
+}
+
+///
+/// Every item of a list endpoint, fetching pages as they're needed
+///
+pub type PageStream<'a, T> = Pin<Box<dyn Stream<Item = Result<T, RequestError>> + Send + 'a>>;
+
+///
+/// Follows the cursors from `pagination` until a page comes back empty. Walks towards older
+/// items unless only `before` is set, in which case it walks towards newer ones - items are
+/// newest first within each page either way. The stream ends after the first error.
+///
+pub(crate) fn paginate<'a, T, F, Fut>(pagination: Pagination, fetch: F) -> PageStream<'a, T>
+where
+    T: Send + 'a,
+    F: FnMut(Pagination) -> Fut + Send + 'a,
+    Fut: Future<Output = Result<Page<T>, RequestError>> + Send + 'a,
+{
+    let newer = pagination.before.is_some() && pagination.after.is_none();
+    let state = (fetch, Some(pagination), VecDeque::new());
+    Box::pin(stream::unfold(
+        state,
+        move |(mut fetch, mut next, mut items)| async move {
+            loop {
+                if let Some(item) = items.pop_front() {
+                    return Some((Ok(item), (fetch, next, items)));
+                }
+                let pagination = next.take()?;
+                match fetch(pagination.clone()).await {
+                    Ok(page) => {
+                        if !page.items.is_empty() {
+                            next = match (newer, page.before, page.after) {
+                                (true, Some(cursor), _) => Some(Pagination {
+                                    limit: pagination.limit,
+                                    ..Pagination::before(&cursor)
+                                }),
+                                (false, _, Some(cursor)) => Some(Pagination {
+                                    limit: pagination.limit,
+                                    ..Pagination::after(&cursor)
+                                }),
+                                _ => None,
+                            };
+                        }
+                        items.extend(page.items);
+                    }
+                    Err(e) => return Some((Err(e), (fetch, None, items))),
+                }
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn apply_appends_the_cursor_arguments() {
+        assert_eq!(Pagination::default().apply("/fills"), "/fills");
+        assert_eq!(Pagination::after("42").apply("/fills"), "/fills?after=42");
+        let pagination = Pagination {
+            limit: Some(10),
+            ..Pagination::before("7")
+        };
+        assert_eq!(
+            pagination.apply("/fills?product_id=BTC-USD"),
+            "/fills?product_id=BTC-USD&limit=10&before=7"
+        );
+    }
+
+    ///
+    /// Serves items 9 down to 0, newest first, three per page, using the item ids as cursors
+    ///
+    fn fetch(
+        requests: Arc<Mutex<Vec<Pagination>>>,
+        fail_at: Option<u32>,
+    ) -> impl FnMut(Pagination) -> std::future::Ready<Result<Page<u32>, RequestError>> {
+        move |pagination| {
+            requests.lock().unwrap().push(pagination.clone());
+            let cursor = |cursor: &Option<String>| cursor.as_ref().map(|c| c.parse().unwrap());
+            let items: Vec<u32> = match (cursor(&pagination.before), cursor(&pagination.after)) {
+                (_, Some(after)) => (0..after).rev().take(3).collect(),
+                (Some(before), None) => {
+                    let mut items: Vec<u32> = (before + 1..10).take(3).collect();
+                    items.reverse();
+                    items
+                }
+                (None, None) => (0..10).rev().take(3).collect(),
+            };
+            if fail_at.is_some() && items.first().copied() == fail_at {
+                return std::future::ready(Err(RequestError::InternalError("failed".to_string())));
+            }
+            std::future::ready(Ok(Page {
+                before: items.first().map(|id| id.to_string()),
+                after: items.last().map(|id| id.to_string()),
+                items,
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn walks_towards_older_items() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let pagination = Pagination {
+            limit: Some(3),
+            ..Pagination::default()
+        };
+        let items: Vec<u32> = paginate(pagination, fetch(requests.clone(), None))
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(items, vec![9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+
+        let requests = requests.lock().unwrap();
+        let cursors: Vec<_> = requests.iter().map(|p| p.after.as_deref()).collect();
+        assert_eq!(
+            cursors,
+            vec![None, Some("7"), Some("4"), Some("1"), Some("0")]
+        );
+        assert!(requests.iter().all(|p| p.limit == Some(3)));
+    }
+
+    #[tokio::test]
+    async fn walks_towards_newer_items_from_before() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let items: Vec<u32> = paginate(Pagination::before("2"), fetch(requests.clone(), None))
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(items, vec![5, 4, 3, 8, 7, 6, 9]);
+
+        let requests = requests.lock().unwrap();
+        let cursors: Vec<_> = requests.iter().map(|p| p.before.as_deref()).collect();
+        assert_eq!(cursors, vec![Some("2"), Some("5"), Some("8"), Some("9")]);
+    }
+
+    #[tokio::test]
+    async fn stops_after_the_first_error() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let results: Vec<_> = paginate(Pagination::default(), fetch(requests.clone(), Some(6)))
+            .collect()
+            .await;
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(Result::is_ok));
+        assert!(results[3].is_err());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
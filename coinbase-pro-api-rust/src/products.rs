@@ -0,0 +1,315 @@
+use crate::errors::InvalidOrderReason;
+use crate::orders::{NewOrder, OrderType, Side, TimeInForce};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+///
+/// Trading pair and the rules orders for it have to follow
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Product {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub base_currency: String,
+    pub quote_currency: String,
+    /// Sizes are multiples of this
+    pub base_increment: Decimal,
+    /// Prices are multiples of this
+    pub quote_increment: Decimal,
+    pub base_min_size: Option<Decimal>,
+    pub base_max_size: Option<Decimal>,
+    pub min_market_funds: Option<Decimal>,
+    pub max_market_funds: Option<Decimal>,
+    pub status: String,
+    pub status_message: Option<String>,
+    #[serde(default)]
+    pub post_only: bool,
+    #[serde(default)]
+    pub limit_only: bool,
+    #[serde(default)]
+    pub cancel_only: bool,
+    #[serde(default)]
+    pub trading_disabled: bool,
+}
+
+///
+/// Rounds down to a multiple of `increment`
+///
+fn floor_to(value: Decimal, increment: Decimal) -> Decimal {
+    if increment <= Decimal::ZERO {
+        return value;
+    }
+    ((value / increment).floor() * increment).normalize()
+}
+
+///
+/// Rounds up to a multiple of `increment`
+///
+fn ceil_to(value: Decimal, increment: Decimal) -> Decimal {
+    if increment <= Decimal::ZERO {
+        return value;
+    }
+    ((value / increment).ceil() * increment).normalize()
+}
+
+impl Product {
+    pub fn is_trading(&self) -> bool {
+        self.status == "online" && !self.trading_disabled
+    }
+
+    ///
+    /// Checks an order against the product's rules, returning it with sizes and funds rounded
+    /// down to the increments, and prices rounded in the order's favour - down for buys and up
+    /// for sells.
+    ///
+    pub fn validate_order(&self, order: &NewOrder) -> Result<NewOrder, InvalidOrderReason> {
+        let invalid = |option: &str| Err(InvalidOrderReason::InvalidOption(option.to_string()));
+        if !self.is_trading() {
+            return Err(InvalidOrderReason::NotTrading(self.status.clone()));
+        }
+        if self.cancel_only {
+            return Err(InvalidOrderReason::CancelOnly);
+        }
+        if self.limit_only && order.order_type != OrderType::Limit {
+            return Err(InvalidOrderReason::LimitOnly);
+        }
+        if self.post_only && !(order.order_type == OrderType::Limit && order.post_only) {
+            return Err(InvalidOrderReason::PostOnly);
+        }
+
+        let mut valid = order.clone();
+        match order.order_type {
+            OrderType::Limit => {
+                if order.price.is_none() {
+                    return Err(InvalidOrderReason::MissingPrice);
+                }
+                if order.size.is_none() {
+                    return Err(InvalidOrderReason::MissingSize);
+                }
+                if order.funds.is_some() {
+                    return invalid("funds are only for market orders");
+                }
+            }
+            OrderType::Market => {
+                match (order.size, order.funds) {
+                    (None, None) => return Err(InvalidOrderReason::MissingSize),
+                    (Some(_), Some(_)) => return invalid("market order with both size and funds"),
+                    _ => {}
+                }
+                if order.post_only {
+                    return invalid("post only market order");
+                }
+                if order.time_in_force.is_some() {
+                    return invalid("time in force is only for limit orders");
+                }
+                valid.price = None;
+            }
+            OrderType::Stop => {
+                return invalid("stop orders are limit or market orders with a stop")
+            }
+        }
+        if order.cancel_after.is_some() && order.time_in_force != Some(TimeInForce::GoodTillTime) {
+            return invalid("cancel after without good till time");
+        }
+        let takes_only = matches!(
+            order.time_in_force,
+            Some(TimeInForce::ImmediateOrCancel) | Some(TimeInForce::FillOrKill)
+        );
+        if order.post_only && takes_only {
+            return invalid("post only with immediate or cancel / fill or kill");
+        }
+        if order.stop.is_some() != order.stop_price.is_some() {
+            return invalid("stop needs both a direction and a stop price");
+        }
+
+        let round_price = |price: Decimal| match order.side {
+            Side::Buy => floor_to(price, self.quote_increment),
+            Side::Sell => ceil_to(price, self.quote_increment),
+        };
+        valid.price = valid.price.map(round_price);
+        valid.stop_price = order.stop_price.map(round_price);
+        valid.size = order.size.map(|size| floor_to(size, self.base_increment));
+        valid.funds = order
+            .funds
+            .map(|funds| floor_to(funds, self.quote_increment));
+
+        if let Some(size) = valid.size {
+            let min_size = self.base_min_size.unwrap_or(self.base_increment);
+            if size <= Decimal::ZERO || size < min_size {
+                return Err(InvalidOrderReason::SizeTooSmall { min: min_size });
+            }
+            if let Some(max) = self.base_max_size {
+                if size > max {
+                    return Err(InvalidOrderReason::SizeTooLarge { max });
+                }
+            }
+        }
+        // market funds limits don't apply to limit orders, which can't carry funds
+        if let Some(funds) = valid.funds {
+            if funds <= Decimal::ZERO {
+                return Err(InvalidOrderReason::FundsTooSmall {
+                    min: self.min_market_funds.unwrap_or(self.quote_increment),
+                });
+            }
+            if let Some(min) = self.min_market_funds {
+                if funds < min {
+                    return Err(InvalidOrderReason::FundsTooSmall { min });
+                }
+            }
+            if let Some(max) = self.max_market_funds {
+                if funds > max {
+                    return Err(InvalidOrderReason::FundsTooLarge { max });
+                }
+            }
+        }
+        Ok(valid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::StopDirection;
+    use rust_decimal::prelude::FromStr;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn product() -> Product {
+        Product {
+            id: "BTC-USD".to_string(),
+            display_name: None,
+            base_currency: "BTC".to_string(),
+            quote_currency: "USD".to_string(),
+            base_increment: dec("0.001"),
+            quote_increment: dec("0.01"),
+            base_min_size: Some(dec("0.01")),
+            base_max_size: Some(dec("100")),
+            min_market_funds: Some(dec("10")),
+            max_market_funds: Some(dec("1000")),
+            status: "online".to_string(),
+            status_message: None,
+            post_only: false,
+            limit_only: false,
+            cancel_only: false,
+            trading_disabled: false,
+        }
+    }
+
+    #[test]
+    fn rounds_prices_in_the_orders_favour() {
+        let product = product();
+        let buy = NewOrder::limit("BTC-USD", Side::Buy, dec("100.129"), dec("1.2345"));
+        let valid = product.validate_order(&buy).unwrap();
+        assert_eq!(valid.price, Some(dec("100.12")));
+        assert_eq!(valid.size, Some(dec("1.234")));
+
+        let sell = NewOrder::limit("BTC-USD", Side::Sell, dec("100.121"), dec("1"))
+            .stop(StopDirection::Entry, dec("99.991"));
+        let valid = product.validate_order(&sell).unwrap();
+        assert_eq!(valid.price, Some(dec("100.13")));
+        assert_eq!(valid.stop_price, Some(dec("100")));
+    }
+
+    #[test]
+    fn rounds_funds_down() {
+        let order = NewOrder::market_funds("BTC-USD", Side::Buy, dec("50.019"));
+        let valid = product().validate_order(&order).unwrap();
+        assert_eq!(valid.funds, Some(dec("50.01")));
+    }
+
+    #[test]
+    fn rejects_sizes_outside_the_limits() {
+        let product = product();
+        let small = NewOrder::limit("BTC-USD", Side::Buy, dec("100"), dec("0.0099"));
+        assert_eq!(
+            product.validate_order(&small),
+            Err(InvalidOrderReason::SizeTooSmall { min: dec("0.01") })
+        );
+        let large = NewOrder::market("BTC-USD", Side::Sell, dec("101"));
+        assert_eq!(
+            product.validate_order(&large),
+            Err(InvalidOrderReason::SizeTooLarge { max: dec("100") })
+        );
+    }
+
+    #[test]
+    fn checks_market_funds_only_for_market_orders() {
+        let product = product();
+        let small = NewOrder::market_funds("BTC-USD", Side::Buy, dec("5"));
+        assert_eq!(
+            product.validate_order(&small),
+            Err(InvalidOrderReason::FundsTooSmall { min: dec("10") })
+        );
+        let large = NewOrder::market_funds("BTC-USD", Side::Buy, dec("5000"));
+        assert_eq!(
+            product.validate_order(&large),
+            Err(InvalidOrderReason::FundsTooLarge { max: dec("1000") })
+        );
+        // a limit order's notional isn't held to the market funds limits
+        let limit = NewOrder::limit("BTC-USD", Side::Buy, dec("100"), dec("50"));
+        assert!(product.validate_order(&limit).is_ok());
+        let limit = NewOrder::limit("BTC-USD", Side::Buy, dec("1"), dec("1"));
+        assert!(product.validate_order(&limit).is_ok());
+    }
+
+    #[test]
+    fn rejects_orders_the_product_does_not_accept() {
+        let order = NewOrder::market("BTC-USD", Side::Buy, dec("1"));
+
+        let mut product = product();
+        product.status = "delisted".to_string();
+        assert_eq!(
+            product.validate_order(&order),
+            Err(InvalidOrderReason::NotTrading("delisted".to_string()))
+        );
+
+        let mut product = self::product();
+        product.cancel_only = true;
+        assert_eq!(
+            product.validate_order(&order),
+            Err(InvalidOrderReason::CancelOnly)
+        );
+
+        let mut product = self::product();
+        product.limit_only = true;
+        assert_eq!(
+            product.validate_order(&order),
+            Err(InvalidOrderReason::LimitOnly)
+        );
+    }
+
+    #[test]
+    fn post_only_products_need_post_only_limit_orders() {
+        let mut product = product();
+        product.post_only = true;
+        let limit = NewOrder::limit("BTC-USD", Side::Buy, dec("100"), dec("1"));
+        assert_eq!(
+            product.validate_order(&limit),
+            Err(InvalidOrderReason::PostOnly)
+        );
+        assert_eq!(
+            product.validate_order(&NewOrder::market("BTC-USD", Side::Buy, dec("1"))),
+            Err(InvalidOrderReason::PostOnly)
+        );
+        assert!(product.validate_order(&limit.post_only()).is_ok());
+    }
+
+    #[test]
+    fn rejects_conflicting_options() {
+        let product = product();
+        let market = NewOrder::market("BTC-USD", Side::Buy, dec("1")).post_only();
+        assert!(matches!(
+            product.validate_order(&market),
+            Err(InvalidOrderReason::InvalidOption(_))
+        ));
+        let limit = NewOrder::limit("BTC-USD", Side::Buy, dec("100"), dec("1"))
+            .time_in_force(TimeInForce::ImmediateOrCancel)
+            .post_only();
+        assert!(matches!(
+            product.validate_order(&limit),
+            Err(InvalidOrderReason::InvalidOption(_))
+        ));
+    }
+}
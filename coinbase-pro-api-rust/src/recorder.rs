@@ -0,0 +1,473 @@
+use crate::errors::{FeedError, RecordError};
+use crate::feed::FeedMessage;
+use crate::websocket::{FeedConnection, RawFeedEvent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use zstd::stream::read::Decoder;
+use zstd::stream::write::Encoder;
+
+/// Directory for messages that don't belong to a product, e.g. `subscriptions`
+const FEED_DIRECTORY: &str = "feed";
+
+///
+/// Feed message read back from a recording
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMessage {
+    /// When the message arrived, which drives the clock during a replay
+    pub received: DateTime<Utc>,
+    /// Recorder run that captured the message and the message's position within the run, so
+    /// arrival order survives splitting messages across files and restarting the recorder
+    pub session: u64,
+    pub index: u64,
+    /// The message's JSON exactly as it was received
+    pub raw: String,
+}
+
+impl RecordedMessage {
+    ///
+    /// Record of a typed message, e.g. for building a backtest in code
+    ///
+    pub fn new(received: DateTime<Utc>, index: u64, message: &FeedMessage) -> Self {
+        RecordedMessage {
+            received,
+            session: 0,
+            index,
+            raw: serde_json::to_string(message).unwrap_or_default(),
+        }
+    }
+
+    ///
+    /// The typed message, or `None` if it is of a type this version doesn't know
+    ///
+    pub fn message(&self) -> Option<FeedMessage> {
+        serde_json::from_str(&self.raw).ok()
+    }
+}
+
+///
+/// Line written by the recorder - keeps the message exactly as it was received
+///
+#[derive(Serialize, Deserialize)]
+struct RecordLine<'a> {
+    received: DateTime<Utc>,
+    #[serde(default)]
+    session: u64,
+    #[serde(default)]
+    index: u64,
+    #[serde(borrow)]
+    message: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct ProductId<'a> {
+    #[serde(borrow)]
+    product_id: Option<&'a str>,
+}
+
+///
+/// File the current hour of one product's messages goes to
+///
+struct RecordFile {
+    hour: String,
+    file: Option<File>,
+    encoder: Option<Encoder<'static, File>>,
+}
+
+impl RecordFile {
+    ///
+    /// Starts a new file for the hour, or a numbered part if an earlier run already wrote one
+    ///
+    fn create(directory: &Path, hour: &str) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let mut path = directory.join(format!("{}.jsonl.zst", hour));
+        let mut part = 1;
+        while path.exists() {
+            path = directory.join(format!("{}.{}.jsonl.zst", hour, part));
+            part += 1;
+        }
+        Ok(RecordFile {
+            hour: hour.to_string(),
+            file: Some(File::create(path)?),
+            encoder: None,
+        })
+    }
+
+    // `io::Error::other` needs a newer compiler than the crate supports
+    #[allow(clippy::io_other_error)]
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.encoder.is_none() {
+            if let Some(file) = self.file.take() {
+                self.encoder = Some(Encoder::new(file, 0)?);
+            }
+        }
+        match self.encoder.as_mut() {
+            Some(encoder) => encoder.write_all(line),
+            None => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "recording file was lost",
+            )),
+        }
+    }
+
+    ///
+    /// Ends the current zstd frame so everything written so far can be read back even if the
+    /// recorder is killed before the next flush
+    ///
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            let mut file = encoder.finish()?;
+            file.flush()?;
+            self.file = Some(file);
+        }
+        Ok(())
+    }
+}
+
+///
+/// Writes every message from a feed to zstd-compressed JSON lines, one file per product per
+/// hour under `directory/<product_id>/`
+///
+pub struct Recorder {
+    feed: FeedConnection,
+    directory: PathBuf,
+    session: u64,
+    index: u64,
+    files: HashMap<String, RecordFile>,
+    flush_interval: Duration,
+    last_flush: Instant,
+}
+
+impl Recorder {
+    pub fn new<P: Into<PathBuf>>(feed: FeedConnection, directory: P) -> Self {
+        Recorder {
+            feed,
+            directory: directory.into(),
+            session: Utc::now().timestamp_millis() as u64,
+            index: 0,
+            files: HashMap::new(),
+            flush_interval: Duration::from_secs(1),
+            last_flush: Instant::now(),
+        }
+    }
+
+    ///
+    /// Identifies this run in the recorded messages - later runs have larger sessions
+    ///
+    pub fn session(&self) -> u64 {
+        self.session
+    }
+
+    ///
+    /// Most data lost if the recorder is killed. Defaults to a second.
+    ///
+    pub fn set_flush_interval(&mut self, interval: Duration) {
+        self.flush_interval = interval;
+    }
+
+    pub async fn start(
+        &mut self,
+        product_ids: &[&str],
+        channels: &[&str],
+    ) -> Result<(), RecordError> {
+        self.feed.subscribe(product_ids, channels).await;
+        self.feed.connect().await?;
+        Ok(())
+    }
+
+    ///
+    /// Records until the feed is closed or gives up reconnecting
+    ///
+    pub async fn run(&mut self) -> Result<(), RecordError> {
+        loop {
+            match self.feed.next_raw_event().await {
+                Ok(RawFeedEvent::Message(text)) => self.record(&text)?,
+                Ok(RawFeedEvent::Connection(_)) => continue,
+                Err(FeedError::NotConnected) => return Ok(self.flush()?),
+                Err(e) => {
+                    self.flush()?;
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    pub async fn close(&mut self) -> Result<(), RecordError> {
+        self.feed.disconnect().await;
+        Ok(self.flush()?)
+    }
+
+    ///
+    /// Writes one message, received now
+    ///
+    pub fn record(&mut self, text: &str) -> Result<(), RecordError> {
+        self.record_at(text, Utc::now())
+    }
+
+    fn record_at(&mut self, text: &str, received: DateTime<Utc>) -> Result<(), RecordError> {
+        let message: &RawValue =
+            serde_json::from_str(text).map_err(|e| RecordError::InvalidMessage(e.to_string()))?;
+        let product_id: ProductId = serde_json::from_str(message.get())
+            .map_err(|e| RecordError::InvalidMessage(e.to_string()))?;
+        let mut line = serde_json::to_vec(&RecordLine {
+            received,
+            session: self.session,
+            index: self.index,
+            message,
+        })
+        .map_err(|e| RecordError::InvalidMessage(e.to_string()))?;
+        line.push(b'\n');
+        self.index += 1;
+
+        let product_id = product_id.product_id.unwrap_or(FEED_DIRECTORY);
+        let hour = received.format("%Y-%m-%dT%H").to_string();
+        let rotate = match self.files.get(product_id) {
+            Some(file) => file.hour != hour,
+            None => true,
+        };
+        if rotate {
+            if let Some(mut file) = self.files.remove(product_id) {
+                file.flush()?;
+            }
+            let file = RecordFile::create(&self.directory.join(product_id), &hour)?;
+            self.files.insert(product_id.to_string(), file);
+        }
+        if let Some(file) = self.files.get_mut(product_id) {
+            file.write(&line)?;
+        }
+
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for file in self.files.values_mut() {
+            file.flush()?;
+        }
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+///
+/// Reads one recorded file - zstd-compressed if it ends in `.zst`, plain JSON lines otherwise.
+/// A file cut short by the recorder being killed is read up to where it ends, and lines that
+/// aren't records are skipped.
+///
+pub fn read_records<P: AsRef<Path>>(path: P) -> Result<Vec<RecordedMessage>, RecordError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = if path.extension() == Some(OsStr::new("zst")) {
+        Box::new(Decoder::new(file)?)
+    } else {
+        Box::new(file)
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Ok(record) = serde_json::from_str::<RecordLine>(&line) {
+            records.push(RecordedMessage {
+                received: record.received,
+                session: record.session,
+                index: record.index,
+                raw: record.message.get().to_string(),
+            });
+        }
+    }
+    Ok(records)
+}
+
+///
+/// Reads several files, e.g. one per product, merged back into the order they arrived in
+///
+pub fn load_records<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<RecordedMessage>, RecordError> {
+    let mut records = Vec::new();
+    for path in paths {
+        records.extend(read_records(path)?);
+    }
+    records.sort_by_key(|record| (record.session, record.index, record.received));
+    Ok(records)
+}
+
+///
+/// Every recorded file under `directory`, e.g. the directory a `Recorder` wrote to
+///
+pub fn find_records<P: AsRef<Path>>(directory: P) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            paths.extend(find_records(&path)?);
+        } else if path.to_string_lossy().ends_with(".jsonl")
+            || path.to_string_lossy().ends_with(".jsonl.zst")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const HEARTBEAT: &str = r#"{"type":"heartbeat","sequence":90,"last_trade_id":20,"product_id":"BTC-USD","time":"2021-05-01T12:00:00.000000Z"}"#;
+    const SUBSCRIPTIONS: &str =
+        r#"{"type":"subscriptions","channels":[{"name":"heartbeat","product_ids":["BTC-USD"]}]}"#;
+
+    ///
+    /// Empty directory of its own for each test
+    ///
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("recorder-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&directory).ok();
+        directory
+    }
+
+    fn recorder(directory: &Path) -> Recorder {
+        Recorder::new(FeedConnection::new("ws://127.0.0.1:1"), directory)
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 5, 1, hour, minute, 0).unwrap()
+    }
+
+    fn file_names(directory: &Path) -> Vec<String> {
+        find_records(directory)
+            .unwrap()
+            .iter()
+            .map(|path| {
+                path.strip_prefix(directory)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_back_what_was_recorded() {
+        let directory = directory("round-trip");
+        let mut recorder = recorder(&directory);
+        recorder.record_at(SUBSCRIPTIONS, at(12, 0)).unwrap();
+        recorder.record_at(HEARTBEAT, at(12, 1)).unwrap();
+        recorder.flush().unwrap();
+
+        let product = read_records(directory.join("BTC-USD/2021-05-01T12.jsonl.zst")).unwrap();
+        assert_eq!(product.len(), 1);
+        assert_eq!(product[0].received, at(12, 1));
+        assert_eq!(product[0].session, recorder.session());
+        assert_eq!(product[0].index, 1);
+        assert_eq!(product[0].raw, HEARTBEAT);
+        assert!(matches!(
+            product[0].message(),
+            Some(FeedMessage::Heartbeat(heartbeat)) if heartbeat.sequence == 90
+        ));
+
+        let feed = read_records(directory.join("feed/2021-05-01T12.jsonl.zst")).unwrap();
+        assert_eq!(feed.len(), 1);
+        assert_eq!(feed[0].index, 0);
+        assert_eq!(feed[0].raw, SUBSCRIPTIONS);
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn starts_a_file_each_hour_and_numbers_parts_from_later_runs() {
+        let directory = directory("rotation");
+        let mut first = recorder(&directory);
+        first.record_at(HEARTBEAT, at(12, 59)).unwrap();
+        first.record_at(HEARTBEAT, at(13, 0)).unwrap();
+        first.flush().unwrap();
+        for _ in 0..2 {
+            let mut restarted = recorder(&directory);
+            restarted.record_at(HEARTBEAT, at(13, 30)).unwrap();
+            restarted.flush().unwrap();
+        }
+
+        assert_eq!(
+            file_names(&directory),
+            vec![
+                "BTC-USD/2021-05-01T12.jsonl.zst",
+                "BTC-USD/2021-05-01T13.1.jsonl.zst",
+                "BTC-USD/2021-05-01T13.2.jsonl.zst",
+                "BTC-USD/2021-05-01T13.jsonl.zst",
+            ]
+        );
+        let records = load_records(&find_records(&directory).unwrap()).unwrap();
+        assert_eq!(records.len(), 4);
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn skips_lines_that_are_not_records() {
+        let directory = directory("unparseable");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("mixed.jsonl");
+        let unknown = r#"{"type":"auction","product_id":"BTC-USD"}"#;
+        let lines = [
+            format!(
+                r#"{{"received":"2021-05-01T12:00:00Z","message":{}}}"#,
+                unknown
+            ),
+            "not json".to_string(),
+            String::new(),
+            format!(
+                r#"{{"received":"2021-05-01T12:01:00Z","message":{}}}"#,
+                HEARTBEAT
+            ),
+        ];
+        fs::write(&path, lines.join("\n")).unwrap();
+
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        // a message type this version doesn't know is kept, but has no typed message
+        assert_eq!(records[0].raw, unknown);
+        assert_eq!(records[0].message(), None);
+        assert!(records[1].message().is_some());
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn loads_files_in_arrival_order() {
+        let directory = directory("ordering");
+        fs::create_dir_all(&directory).unwrap();
+        let line = |session: u64, index: u64, minute: u32| {
+            format!(
+                r#"{{"received":"2021-05-01T12:{:02}:00Z","session":{},"index":{},"message":{}}}"#,
+                minute, session, index, HEARTBEAT
+            )
+        };
+        // a later session wins over an earlier clock, e.g. after the clock was set back
+        let eth = [line(2, 0, 0), line(1, 3, 9)].join("\n");
+        let btc = [line(1, 1, 5), line(2, 1, 1), line(1, 0, 5)].join("\n");
+        fs::write(directory.join("eth.jsonl"), eth).unwrap();
+        fs::write(directory.join("btc.jsonl"), btc).unwrap();
+
+        let records = load_records(&find_records(&directory).unwrap()).unwrap();
+        let order: Vec<(u64, u64)> = records
+            .iter()
+            .map(|record| (record.session, record.index))
+            .collect();
+        assert_eq!(order, vec![(1, 0), (1, 1), (1, 3), (2, 0), (2, 1)]);
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,424 @@
+use crate::errors::RecordError;
+use crate::feed::{Channel, FeedMessage, Subscriptions};
+use crate::recorder::{load_records, RecordedMessage};
+use chrono::{DateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::future;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep_until, Instant};
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+///
+/// How fast recorded messages are played back
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackSpeed {
+    /// With the same gaps between messages as when they were recorded
+    RealTime,
+    /// Gaps divided by the factor, e.g. `Accelerated(100.0)` plays an hour in 36 seconds
+    Accelerated(f64),
+    /// No gaps at all
+    AsFastAsPossible,
+}
+
+///
+/// Waits until each recorded message is due, measured from when the first one was played
+///
+struct Playback {
+    speed: PlaybackSpeed,
+    start: Option<(Instant, DateTime<Utc>)>,
+}
+
+impl Playback {
+    fn new(speed: PlaybackSpeed) -> Self {
+        Playback { speed, start: None }
+    }
+
+    async fn wait(&mut self, received: DateTime<Utc>) {
+        let factor = match self.speed {
+            PlaybackSpeed::RealTime => 1.0,
+            PlaybackSpeed::Accelerated(factor) if factor > 0.0 => factor,
+            _ => return,
+        };
+        let (started, first) = *self.start.get_or_insert((Instant::now(), received));
+        if let Ok(elapsed) = (received - first).to_std() {
+            sleep_until(started + elapsed.div_f64(factor)).await;
+        }
+    }
+}
+
+///
+/// Plays recorded messages back in process, e.g. for `Strategy::replay`
+///
+pub struct Replayer {
+    records: VecDeque<RecordedMessage>,
+    playback: Playback,
+}
+
+impl Replayer {
+    pub fn new(records: Vec<RecordedMessage>, speed: PlaybackSpeed) -> Self {
+        Replayer {
+            records: records.into(),
+            playback: Playback::new(speed),
+        }
+    }
+
+    pub fn from_files<P: AsRef<Path>>(
+        paths: &[P],
+        speed: PlaybackSpeed,
+    ) -> Result<Self, RecordError> {
+        Ok(Replayer::new(load_records(paths)?, speed))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.records.len()
+    }
+
+    ///
+    /// Waits until the next message is due, or returns `None` once every message was played
+    ///
+    pub async fn next_message(&mut self) -> Option<RecordedMessage> {
+        let received = self.records.front()?.received;
+        self.playback.wait(received).await;
+        self.records.pop_front()
+    }
+}
+
+#[derive(Deserialize)]
+struct SubscribeRequest {
+    #[serde(rename = "type")]
+    request_type: String,
+    #[serde(default)]
+    product_ids: Vec<String>,
+    #[serde(default)]
+    channels: Vec<ChannelRequest>,
+}
+
+///
+/// Channels can be subscribed to by name, or with their own product ids
+///
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChannelRequest {
+    Name(String),
+    Channel {
+        name: String,
+        #[serde(default)]
+        product_ids: Vec<String>,
+    },
+}
+
+///
+/// Channels that carry a message
+///
+fn channels(message: &FeedMessage) -> &'static [&'static str] {
+    match message {
+        FeedMessage::Heartbeat(_) => &["heartbeat"],
+        FeedMessage::Ticker(_) => &["ticker"],
+        FeedMessage::Snapshot(_) | FeedMessage::L2Update(_) => &["level2"],
+        FeedMessage::Match(_) => &["matches", "full"],
+        FeedMessage::Received(_)
+        | FeedMessage::Open(_)
+        | FeedMessage::Done(_)
+        | FeedMessage::Change(_)
+        | FeedMessage::Activate(_) => &["full"],
+        FeedMessage::Status(_) => &["status"],
+        // the server answers subscriptions itself
+        FeedMessage::Subscriptions(_) | FeedMessage::Error(_) => &[],
+    }
+}
+
+///
+/// Local websocket server speaking the feed protocol, so any feed client can be pointed at
+/// recorded data. Every connection is played the recording from the start once it subscribes,
+/// filtered to its subscriptions, and stays open without further messages once it is done.
+///
+pub struct ReplayServer {
+    records: Arc<Vec<RecordedMessage>>,
+    speed: PlaybackSpeed,
+}
+
+impl ReplayServer {
+    pub fn new(records: Vec<RecordedMessage>, speed: PlaybackSpeed) -> Self {
+        ReplayServer {
+            records: Arc::new(records),
+            speed,
+        }
+    }
+
+    ///
+    /// Serves connections until the listener fails - bind to port 0 and use `local_addr` for a
+    /// free port, then connect with e.g. `Environment::custom(rest_url, "ws://127.0.0.1:<port>")`
+    ///
+    pub async fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _address) = listener.accept().await?;
+            let records = self.records.clone();
+            let speed = self.speed;
+            tokio::spawn(async move {
+                serve_connection(stream, records, speed).await.ok();
+            });
+        }
+    }
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    records: Arc<Vec<RecordedMessage>>,
+    speed: PlaybackSpeed,
+) -> Result<(), Error> {
+    let mut socket = accept_async(stream).await?;
+    let mut subscriptions: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut playback = Playback::new(speed);
+    let mut position = 0;
+    loop {
+        // playback starts with the first subscription
+        let next = records.get(position).filter(|_| !subscriptions.is_empty());
+        let due = async {
+            match next {
+                Some(record) => playback.wait(record.received).await,
+                None => future::pending().await,
+            }
+        };
+        tokio::select! {
+            incoming = socket.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(request) = serde_json::from_str(&text) {
+                        update_subscriptions(&mut subscriptions, request);
+                        let reply = FeedMessage::Subscriptions(Subscriptions {
+                            channels: subscriptions
+                                .iter()
+                                .map(|(name, product_ids)| Channel {
+                                    name: name.clone(),
+                                    product_ids: product_ids.iter().cloned().collect(),
+                                })
+                                .collect(),
+                        });
+                        socket.send(Message::Text(serde_json::to_string(&reply).unwrap_or_default())).await?;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(_)) => {}
+            },
+            _ = due => {
+                if let Some(record) = next {
+                    position += 1;
+                    match record.message() {
+                        // send the recorded JSON untouched, so fields the typed message
+                        // doesn't model reach the client too
+                        Some(message) if is_subscribed(&subscriptions, &message) => {
+                            socket.send(Message::Text(record.raw.clone())).await?;
+                        }
+                        _ => {}
+                    }
+                }
+            },
+        }
+    }
+}
+
+fn update_subscriptions(
+    subscriptions: &mut BTreeMap<String, BTreeSet<String>>,
+    request: SubscribeRequest,
+) {
+    for channel in request.channels {
+        let (name, product_ids) = match channel {
+            ChannelRequest::Name(name) => (name, request.product_ids.clone()),
+            ChannelRequest::Channel { name, product_ids } if product_ids.is_empty() => {
+                (name, request.product_ids.clone())
+            }
+            ChannelRequest::Channel { name, product_ids } => (name, product_ids),
+        };
+        if request.request_type == "unsubscribe" {
+            if let Some(products) = subscriptions.get_mut(&name) {
+                for product_id in &product_ids {
+                    products.remove(product_id);
+                }
+                if products.is_empty() {
+                    subscriptions.remove(&name);
+                }
+            }
+        } else if request.request_type == "subscribe" {
+            subscriptions.entry(name).or_default().extend(product_ids);
+        }
+    }
+}
+
+fn is_subscribed(
+    subscriptions: &BTreeMap<String, BTreeSet<String>>,
+    message: &FeedMessage,
+) -> bool {
+    channels(message).iter().any(|channel| {
+        match (subscriptions.get(*channel), message.product_id()) {
+            (Some(products), Some(product_id)) => products.contains(product_id),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::Status;
+    use chrono::{Duration, TimeZone};
+    use serde_json::json;
+
+    fn at(second: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 5, 1, 12, 0, 0).unwrap() + Duration::seconds(second)
+    }
+
+    ///
+    /// Time the playback waited, in seconds of tokio's paused clock, for each message
+    ///
+    async fn waits(speed: PlaybackSpeed, received: &[i64]) -> Vec<f64> {
+        let mut playback = Playback::new(speed);
+        let mut waits = Vec::new();
+        for second in received {
+            let before = Instant::now();
+            playback.wait(at(*second)).await;
+            waits.push((Instant::now() - before).as_secs_f64());
+        }
+        waits
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn real_time_keeps_the_recorded_gaps() {
+        let waits = waits(PlaybackSpeed::RealTime, &[100, 101, 101, 110]).await;
+        assert_eq!(waits, vec![0.0, 1.0, 0.0, 9.0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accelerated_divides_the_gaps() {
+        let waits = waits(PlaybackSpeed::Accelerated(10.0), &[0, 10, 30]).await;
+        assert_eq!(waits, vec![0.0, 1.0, 2.0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn measures_from_the_first_message_played() {
+        let mut playback = Playback::new(PlaybackSpeed::RealTime);
+        playback.wait(at(0)).await;
+        // time spent between messages counts towards the next gap
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        let before = Instant::now();
+        playback.wait(at(5)).await;
+        assert_eq!((Instant::now() - before).as_secs(), 2);
+        // messages recorded before the first don't wait
+        let before = Instant::now();
+        playback.wait(at(-5)).await;
+        assert_eq!(Instant::now(), before);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn as_fast_as_possible_never_waits() {
+        let fastest = waits(PlaybackSpeed::AsFastAsPossible, &[0, 60, 3600]).await;
+        assert_eq!(fastest, vec![0.0, 0.0, 0.0]);
+        // as does a factor that isn't positive
+        let zero_factor = waits(PlaybackSpeed::Accelerated(0.0), &[0, 60]).await;
+        assert_eq!(zero_factor, vec![0.0, 0.0]);
+    }
+
+    fn request(request: serde_json::Value) -> SubscribeRequest {
+        serde_json::from_value(request).unwrap()
+    }
+
+    fn heartbeat(product_id: &str) -> FeedMessage {
+        serde_json::from_value(json!({
+            "type": "heartbeat",
+            "sequence": 90,
+            "last_trade_id": 20,
+            "product_id": product_id,
+            "time": "2021-05-01T12:00:00Z"
+        }))
+        .unwrap()
+    }
+
+    fn l2update(product_id: &str) -> FeedMessage {
+        serde_json::from_value(json!({
+            "type": "l2update",
+            "product_id": product_id,
+            "time": "2021-05-01T12:00:00Z",
+            "changes": [["buy", "100", "1"]]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn subscribes_by_channel_name_or_with_its_own_products() {
+        let mut subscriptions = BTreeMap::new();
+        update_subscriptions(
+            &mut subscriptions,
+            request(json!({
+                "type": "subscribe",
+                "product_ids": ["BTC-USD", "ETH-USD"],
+                "channels": ["heartbeat", {"name": "level2", "product_ids": ["ETH-USD"]}]
+            })),
+        );
+        assert!(is_subscribed(&subscriptions, &heartbeat("BTC-USD")));
+        assert!(is_subscribed(&subscriptions, &heartbeat("ETH-USD")));
+        assert!(is_subscribed(&subscriptions, &l2update("ETH-USD")));
+        assert!(!is_subscribed(&subscriptions, &l2update("BTC-USD")));
+        assert!(!is_subscribed(&subscriptions, &heartbeat("LTC-USD")));
+    }
+
+    #[test]
+    fn unsubscribing_drops_products_then_the_channel() {
+        let mut subscriptions = BTreeMap::new();
+        update_subscriptions(
+            &mut subscriptions,
+            request(json!({
+                "type": "subscribe",
+                "product_ids": ["BTC-USD", "ETH-USD"],
+                "channels": ["heartbeat", "level2"]
+            })),
+        );
+        update_subscriptions(
+            &mut subscriptions,
+            request(json!({
+                "type": "unsubscribe",
+                "product_ids": ["BTC-USD"],
+                "channels": ["heartbeat"]
+            })),
+        );
+        assert!(!is_subscribed(&subscriptions, &heartbeat("BTC-USD")));
+        assert!(is_subscribed(&subscriptions, &heartbeat("ETH-USD")));
+        assert!(is_subscribed(&subscriptions, &l2update("BTC-USD")));
+
+        update_subscriptions(
+            &mut subscriptions,
+            request(json!({
+                "type": "unsubscribe",
+                "channels": [{"name": "heartbeat", "product_ids": ["ETH-USD"]}]
+            })),
+        );
+        assert!(!subscriptions.contains_key("heartbeat"));
+        assert!(!is_subscribed(&subscriptions, &heartbeat("ETH-USD")));
+    }
+
+    #[test]
+    fn messages_without_a_channel_are_never_forwarded() {
+        let mut subscriptions = BTreeMap::new();
+        update_subscriptions(
+            &mut subscriptions,
+            request(json!({
+                "type": "subscribe",
+                "product_ids": ["BTC-USD"],
+                "channels": ["heartbeat", "status"]
+            })),
+        );
+        let error = serde_json::from_value(json!({"type": "error", "message": "Failed"})).unwrap();
+        assert!(!is_subscribed(&subscriptions, &error));
+        let status = FeedMessage::Status(Status {
+            products: Vec::new(),
+            currencies: Vec::new(),
+        });
+        // status has no product, so it goes to anyone on the channel
+        assert!(is_subscribed(&subscriptions, &status));
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+///
+/// Break in a product's sequence numbers - messages were dropped or arrived out of order
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub product_id: String,
+    pub expected: u64,
+    pub received: u64,
+}
+
+///
+/// Result of checking a message's sequence number
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceCheck {
+    /// First message seen for the product, or the next one expected
+    InOrder,
+    /// Already seen, or already covered by a snapshot - safe to drop
+    Stale,
+    Gap(SequenceGap),
+}
+
+///
+/// Tracks the last sequence number seen per product.
+///
+/// The `full` channel numbers every message consecutively, so a strict tracker reports any skipped
+/// number as a gap and treats older numbers as stale. Channels such as `ticker` only carry a subset
+/// of the product's sequence, so a monotonic tracker can only report messages that go backwards.
+///
+#[derive(Debug, Clone)]
+pub struct SequenceTracker {
+    strict: bool,
+    last: HashMap<String, u64>,
+}
+
+impl SequenceTracker {
+    pub fn strict() -> Self {
+        SequenceTracker {
+            strict: true,
+            last: HashMap::new(),
+        }
+    }
+
+    pub fn monotonic() -> Self {
+        SequenceTracker {
+            strict: false,
+            last: HashMap::new(),
+        }
+    }
+
+    pub fn check(&mut self, product_id: &str, sequence: u64) -> SequenceCheck {
+        let last = match self.last.get(product_id) {
+            Some(last) => *last,
+            None => {
+                self.last.insert(product_id.to_string(), sequence);
+                return SequenceCheck::InOrder;
+            }
+        };
+        let gap = SequenceGap {
+            product_id: product_id.to_string(),
+            expected: last + 1,
+            received: sequence,
+        };
+        if sequence == last || (self.strict && sequence < last) {
+            SequenceCheck::Stale
+        } else if sequence < last || (self.strict && sequence > last + 1) {
+            SequenceCheck::Gap(gap)
+        } else {
+            self.last.insert(product_id.to_string(), sequence);
+            SequenceCheck::InOrder
+        }
+    }
+
+    pub fn last(&self, product_id: &str) -> Option<u64> {
+        self.last.get(product_id).copied()
+    }
+
+    ///
+    /// Starts tracking again from `sequence`, e.g. after loading a snapshot
+    ///
+    pub fn set(&mut self, product_id: &str, sequence: u64) {
+        self.last.insert(product_id.to_string(), sequence);
+    }
+
+    ///
+    /// Forgets a product so the next message is accepted as in order, e.g. after resubscribing
+    ///
+    pub fn reset(&mut self, product_id: &str) {
+        self.last.remove(product_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_message_is_in_order() {
+        let mut tracker = SequenceTracker::strict();
+        assert_eq!(tracker.check("BTC-USD", 10), SequenceCheck::InOrder);
+        assert_eq!(tracker.last("BTC-USD"), Some(10));
+        assert_eq!(tracker.last("ETH-USD"), None);
+    }
+
+    #[test]
+    fn strict_tracker_reports_skipped_numbers() {
+        let mut tracker = SequenceTracker::strict();
+        tracker.set("BTC-USD", 10);
+        assert_eq!(tracker.check("BTC-USD", 11), SequenceCheck::InOrder);
+        assert_eq!(tracker.check("BTC-USD", 11), SequenceCheck::Stale);
+        assert_eq!(tracker.check("BTC-USD", 5), SequenceCheck::Stale);
+        assert_eq!(
+            tracker.check("BTC-USD", 14),
+            SequenceCheck::Gap(SequenceGap {
+                product_id: "BTC-USD".to_string(),
+                expected: 12,
+                received: 14,
+            })
+        );
+        // a gap does not advance the tracker
+        assert_eq!(tracker.last("BTC-USD"), Some(11));
+    }
+
+    #[test]
+    fn monotonic_tracker_allows_skips_but_not_rewinds() {
+        let mut tracker = SequenceTracker::monotonic();
+        tracker.set("BTC-USD", 10);
+        assert_eq!(tracker.check("BTC-USD", 15), SequenceCheck::InOrder);
+        assert_eq!(tracker.check("BTC-USD", 15), SequenceCheck::Stale);
+        assert_eq!(
+            tracker.check("BTC-USD", 12),
+            SequenceCheck::Gap(SequenceGap {
+                product_id: "BTC-USD".to_string(),
+                expected: 16,
+                received: 12,
+            })
+        );
+    }
+
+    #[test]
+    fn reset_accepts_any_next_number() {
+        let mut tracker = SequenceTracker::strict();
+        tracker.set("BTC-USD", 10);
+        tracker.reset("BTC-USD");
+        assert_eq!(tracker.check("BTC-USD", 3), SequenceCheck::InOrder);
+        assert_eq!(tracker.last("BTC-USD"), Some(3));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::client::AuthorizedClient;
+use crate::errors::FeedError;
+use crate::feed::{Activate, Change, Done, FeedMessage, Match, Open, Received};
+use crate::orders::{Liquidity, Side};
+use crate::websocket::{ConnectionState, FeedConnection, FeedEvent};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, Stream};
+use rust_decimal::Decimal;
+
+///
+/// One of our orders matching against another - seen from our side of the trade
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderFill {
+    pub order_id: String,
+    pub trade_id: u64,
+    pub product_id: String,
+    pub side: Side,
+    pub price: Decimal,
+    pub size: Decimal,
+    pub liquidity: Liquidity,
+    pub fee_rate: Option<Decimal>,
+    pub time: DateTime<Utc>,
+}
+
+impl OrderFill {
+    ///
+    /// The user channel fills in the maker fields when our order was resting and the taker
+    /// fields when it crossed the book
+    ///
+    pub fn from_match(m: &Match) -> Self {
+        let maker = m.maker_user_id.is_some() || m.maker_profile_id.is_some();
+        let (order_id, side, liquidity, fee_rate) = if maker {
+            (
+                &m.maker_order_id,
+                m.side,
+                Liquidity::Maker,
+                m.maker_fee_rate,
+            )
+        } else {
+            (
+                &m.taker_order_id,
+                m.side.opposite(),
+                Liquidity::Taker,
+                m.taker_fee_rate,
+            )
+        };
+        OrderFill {
+            order_id: order_id.clone(),
+            trade_id: m.trade_id,
+            product_id: m.product_id.clone(),
+            side,
+            price: m.price,
+            size: m.size,
+            liquidity,
+            fee_rate,
+            time: m.time,
+        }
+    }
+}
+
+///
+/// State change for one of our own orders
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserEvent {
+    Received(Received),
+    Open(Open),
+    Fill(OrderFill),
+    Done(Done),
+    Change(Change),
+    Activate(Activate),
+    Connection(ConnectionState),
+}
+
+///
+/// Authenticated `user` channel - only carries messages for our own orders
+///
+pub struct UserFeed {
+    feed: FeedConnection,
+    product_ids: Vec<String>,
+}
+
+impl UserFeed {
+    pub fn new(client: &AuthorizedClient) -> Self {
+        UserFeed {
+            feed: client.authenticated_feed(),
+            product_ids: Vec::new(),
+        }
+    }
+
+    pub async fn open(&mut self, product_ids: Vec<&str>) -> Result<(), FeedError> {
+        self.feed.connect().await?;
+        self.feed.subscribe(&product_ids, &["user"]).await;
+        self.product_ids
+            .extend(product_ids.iter().map(|s| s.to_string()));
+        Ok(())
+    }
+
+    pub async fn close(&mut self) {
+        self.feed.disconnect().await;
+    }
+
+    pub fn product_ids(&self) -> &[String] {
+        &self.product_ids
+    }
+
+    ///
+    /// Waits for the next event on our orders. A rejected subscription, e.g. invalid
+    /// credentials, is returned as `FeedError::Rejected`.
+    ///
+    pub async fn next_event(&mut self) -> Result<UserEvent, FeedError> {
+        loop {
+            let message = match self.feed.next_event().await? {
+                FeedEvent::Connection(state) => return Ok(UserEvent::Connection(state)),
+                FeedEvent::Message(message) => message,
+            };
+            let event = match message {
+                FeedMessage::Received(m) => UserEvent::Received(m),
+                FeedMessage::Open(m) => UserEvent::Open(m),
+                FeedMessage::Match(m) => UserEvent::Fill(OrderFill::from_match(&m)),
+                FeedMessage::Done(m) => UserEvent::Done(m),
+                FeedMessage::Change(m) => UserEvent::Change(m),
+                FeedMessage::Activate(m) => UserEvent::Activate(m),
+                FeedMessage::Error(e) => {
+                    return Err(FeedError::Rejected(match e.reason {
+                        Some(reason) => format!("{}: {}", e.message, reason),
+                        None => e.message,
+                    }))
+                }
+                _ => continue,
+            };
+            return Ok(event);
+        }
+    }
+
+    ///
+    /// User events as a stream - ends once the feed is closed or gives up reconnecting
+    ///
+    pub fn events(&mut self) -> impl Stream<Item = Result<UserEvent, FeedError>> + '_ {
+        stream::unfold(self, |feed| async move {
+            match feed.next_event().await {
+                Err(FeedError::NotConnected) => None,
+                event => Some((event, feed)),
+            }
+        })
+    }
+}
@@ -0,0 +1,425 @@
+use crate::credentials::Credentials;
+use crate::errors::FeedError;
+use crate::feed::FeedMessage;
+use futures_util::stream::{self, Stream};
+use futures_util::{SinkExt, StreamExt};
+use rand::Rng;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::{sleep_until, timeout_at, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type Connecting = Pin<Box<dyn Future<Output = Result<Socket, FeedError>> + Send>>;
+
+///
+/// Connection state changes reported alongside feed messages
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connected and all remembered subscriptions were sent
+    Connected,
+    /// Connection lost or idle for too long - the next read reconnects
+    Disconnected { reason: String },
+    /// A reconnect attempt failed - the next read tries again after `delay`
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+        reason: String,
+    },
+}
+
+///
+/// Either a message from the feed or a change in the connection
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedEvent {
+    Message(FeedMessage),
+    Connection(ConnectionState),
+}
+
+///
+/// Feed event with the message left as the JSON text received, e.g. for recording
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawFeedEvent {
+    Message(String),
+    Connection(ConnectionState),
+}
+
+///
+/// How the connection recovers from errors
+///
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Reconnect if no message arrives for this long - subscribe to `heartbeat` to keep quiet
+    /// products from tripping it
+    pub idle_timeout: Duration,
+    /// Give up after this many failed attempts in a row, or never if `None`
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    ///
+    /// Exponential backoff capped at `max_delay`, with jitter so many clients don't reconnect
+    /// in lockstep
+    ///
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+///
+/// Websocket feed connection that remembers its subscriptions and reconnects on failure
+///
+pub struct FeedConnection {
+    url: String,
+    policy: ReconnectPolicy,
+    socket: Option<Socket>,
+    active: bool,
+    attempt: u32,
+    /// Deadlines and the connection attempt in progress are kept here rather than in the
+    /// futures reading the feed, so a read that is dropped part way picks up where it left off
+    idle_deadline: Option<Instant>,
+    reconnect_at: Option<Instant>,
+    connecting: Option<Connecting>,
+    subscriptions: BTreeMap<String, BTreeSet<String>>,
+    credentials: Option<Credentials>,
+}
+
+impl FeedConnection {
+    pub fn new(url: &str) -> Self {
+        FeedConnection::with_policy(url, ReconnectPolicy::default())
+    }
+
+    pub fn with_policy(url: &str, policy: ReconnectPolicy) -> Self {
+        FeedConnection {
+            url: url.to_string(),
+            policy,
+            socket: None,
+            active: false,
+            attempt: 0,
+            idle_deadline: None,
+            reconnect_at: None,
+            connecting: None,
+            subscriptions: BTreeMap::new(),
+            credentials: None,
+        }
+    }
+
+    ///
+    /// Signs every subscribe request, including those replayed after reconnecting
+    ///
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    ///
+    /// Adds the signature fields the feed expects on authenticated subscribe requests
+    ///
+    fn authenticate(&self, request: &mut Value) {
+        if let Some(credentials) = &self.credentials {
+            let time = Credentials::timestamp();
+            request["signature"] = json!(credentials.sign(time, "GET", "/users/self/verify", ""));
+            request["key"] = json!(credentials.access_key());
+            request["passphrase"] = json!(credentials.passphrase());
+            request["timestamp"] = json!(time.to_string());
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    ///
+    /// Opens the connection. If this fails the connection still counts as open and
+    /// `next_event` keeps retrying with backoff.
+    ///
+    pub async fn connect(&mut self) -> Result<(), FeedError> {
+        self.active = true;
+        if self.socket.is_none() {
+            self.open_socket().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn disconnect(&mut self) {
+        self.active = false;
+        self.idle_deadline = None;
+        self.reconnect_at = None;
+        self.connecting = None;
+        if let Some(mut socket) = self.socket.take() {
+            socket.close(None).await.ok();
+        }
+    }
+
+    ///
+    /// Channels and the products subscribed to on each
+    ///
+    pub fn subscriptions(&self) -> &BTreeMap<String, BTreeSet<String>> {
+        &self.subscriptions
+    }
+
+    pub async fn subscribe(&mut self, product_ids: &[&str], channels: &[&str]) {
+        for channel in channels {
+            let products = self.subscriptions.entry(channel.to_string()).or_default();
+            products.extend(product_ids.iter().map(|s| s.to_string()));
+        }
+        // a connection attempt in progress would replay the old subscriptions
+        self.connecting = None;
+        self.send_subscription("subscribe", product_ids, channels)
+            .await;
+    }
+
+    pub async fn unsubscribe(&mut self, product_ids: &[&str], channels: &[&str]) {
+        for channel in channels {
+            if let Some(products) = self.subscriptions.get_mut(*channel) {
+                for product_id in product_ids {
+                    products.remove(*product_id);
+                }
+                if products.is_empty() {
+                    self.subscriptions.remove(*channel);
+                }
+            }
+        }
+        self.connecting = None;
+        self.send_subscription("unsubscribe", product_ids, channels)
+            .await;
+    }
+
+    async fn send_subscription(&mut self, sub_type: &str, product_ids: &[&str], channels: &[&str]) {
+        let mut request = json!({
+            "type": sub_type,
+            "product_ids": product_ids,
+            "channels": channels
+        });
+        if sub_type == "subscribe" {
+            self.authenticate(&mut request);
+        }
+        self.send(&request).await;
+    }
+
+    ///
+    /// Sends a request if connected. A failed write drops the socket so the next read
+    /// reconnects and replays the subscriptions.
+    ///
+    pub(crate) async fn send(&mut self, request: &Value) {
+        if let Some(socket) = self.socket.as_mut() {
+            if socket
+                .send(Message::Text(request.to_string()))
+                .await
+                .is_err()
+            {
+                self.socket = None;
+            }
+        }
+    }
+
+    ///
+    /// Waits for the next feed message or connection state change. Cancel safe - e.g. in
+    /// `tokio::select!` no message is lost, and the idle timeout, reconnect backoff and any
+    /// connection attempt carry on from where they were on the next call.
+    ///
+    pub async fn next_event(&mut self) -> Result<FeedEvent, FeedError> {
+        match self.next_raw_event().await? {
+            RawFeedEvent::Message(text) => serde_json::from_str(&text)
+                .map(FeedEvent::Message)
+                .map_err(|e| FeedError::InvalidMessage(e.to_string())),
+            RawFeedEvent::Connection(state) => Ok(FeedEvent::Connection(state)),
+        }
+    }
+
+    ///
+    /// Like `next_event`, but without parsing the message
+    ///
+    pub async fn next_raw_event(&mut self) -> Result<RawFeedEvent, FeedError> {
+        loop {
+            if !self.active {
+                return Err(FeedError::NotConnected);
+            }
+            let socket = match self.socket.as_mut() {
+                Some(socket) => socket,
+                None => return self.reconnect().await.map(RawFeedEvent::Connection),
+            };
+            let idle_deadline = match self.idle_deadline {
+                Some(deadline) => deadline,
+                None => *self
+                    .idle_deadline
+                    .insert(Instant::now() + self.policy.idle_timeout),
+            };
+            let received = timeout_at(idle_deadline, socket.next()).await;
+            self.idle_deadline = None;
+            let reason = match received {
+                Ok(Some(Ok(Message::Text(text)))) => return Ok(RawFeedEvent::Message(text)),
+                Ok(Some(Ok(_))) => continue,
+                Ok(Some(Err(e))) => e.to_string(),
+                Ok(None) => "connection closed".to_string(),
+                Err(_) => "no message received within idle timeout".to_string(),
+            };
+            self.socket = None;
+            return Ok(RawFeedEvent::Connection(ConnectionState::Disconnected {
+                reason,
+            }));
+        }
+    }
+
+    ///
+    /// Feed events as a stream - ends once the connection is closed or gives up reconnecting
+    ///
+    pub fn events(&mut self) -> impl Stream<Item = Result<FeedEvent, FeedError>> + '_ {
+        stream::unfold(self, |connection| async move {
+            match connection.next_event().await {
+                Err(FeedError::NotConnected) => None,
+                event => Some((event, connection)),
+            }
+        })
+    }
+
+    async fn reconnect(&mut self) -> Result<ConnectionState, FeedError> {
+        // an attempt already under way was interrupted, so resume it rather than starting over
+        if self.connecting.is_none() {
+            if let Some(max_attempts) = self.policy.max_attempts {
+                if self.attempt >= max_attempts {
+                    self.active = false;
+                    return Err(FeedError::Socket(format!(
+                        "gave up reconnecting after {} attempts",
+                        self.attempt
+                    )));
+                }
+            }
+            let reconnect_at = match self.reconnect_at {
+                Some(reconnect_at) => reconnect_at,
+                None => *self
+                    .reconnect_at
+                    .insert(Instant::now() + self.policy.delay(self.attempt)),
+            };
+            sleep_until(reconnect_at).await;
+            self.reconnect_at = None;
+            self.attempt += 1;
+        }
+        match self.open_socket().await {
+            Ok(()) => Ok(ConnectionState::Connected),
+            Err(e) => {
+                let delay = self.policy.delay(self.attempt);
+                self.reconnect_at = Some(Instant::now() + delay);
+                Ok(ConnectionState::Reconnecting {
+                    attempt: self.attempt,
+                    delay,
+                    reason: e.to_string(),
+                })
+            }
+        }
+    }
+
+    async fn open_socket(&mut self) -> Result<(), FeedError> {
+        let connecting = match self.connecting.as_mut() {
+            Some(connecting) => connecting,
+            None => self.connecting.insert(self.start_connecting()),
+        };
+        let result = connecting.await;
+        self.connecting = None;
+        self.socket = Some(result?);
+        self.attempt = 0;
+        self.idle_deadline = None;
+        Ok(())
+    }
+
+    ///
+    /// Connection attempt that replays the current subscriptions once connected
+    ///
+    fn start_connecting(&self) -> Connecting {
+        let url = self.url.clone();
+        let request = if self.subscriptions.is_empty() {
+            None
+        } else {
+            let channels: Vec<Value> = self
+                .subscriptions
+                .iter()
+                .map(|(name, product_ids)| json!({ "name": name, "product_ids": product_ids }))
+                .collect();
+            let mut request = json!({ "type": "subscribe", "channels": channels });
+            self.authenticate(&mut request);
+            Some(request.to_string())
+        };
+        Box::pin(async move {
+            let (mut socket, _response) = connect_async(url.as_str())
+                .await
+                .map_err(|e| FeedError::Socket(e.to_string()))?;
+            if let Some(request) = request {
+                socket
+                    .send(Message::Text(request))
+                    .await
+                    .map_err(|e| FeedError::Socket(e.to_string()))?;
+            }
+            Ok(socket)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            ..ReconnectPolicy::default()
+        }
+    }
+
+    #[test]
+    fn delay_doubles_with_jitter_down_to_half() {
+        let policy = policy();
+        for (attempt, backoff) in [(0, 100), (1, 200), (2, 400), (3, 800)] {
+            let backoff = Duration::from_millis(backoff);
+            for _ in 0..100 {
+                let delay = policy.delay(attempt);
+                assert!(delay >= backoff / 2 && delay <= backoff, "{:?}", delay);
+            }
+        }
+    }
+
+    #[test]
+    fn delay_is_capped_at_max_delay() {
+        let policy = policy();
+        // including attempts whose backoff would overflow
+        for attempt in [4, 10, 31, 32, 1000, u32::MAX] {
+            for _ in 0..100 {
+                let delay = policy.delay(attempt);
+                assert!(
+                    delay >= policy.max_delay / 2 && delay <= policy.max_delay,
+                    "{:?}",
+                    delay
+                );
+            }
+        }
+    }
+}